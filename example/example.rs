@@ -18,16 +18,21 @@ extern crate sdl2;
 extern crate log;
 
 use libc::c_long;
+use media::audioencoder::AudioEncoderConfig;
 use media::audioformat::{ConvertAudioFormat, Float32Interleaved, Float32Planar};
-use media::container::{AudioTrack, ContainerReader, Frame, Track, VideoTrack};
+use media::audioformat::channels::{ChannelLayout, DownmixMatrix};
+use media::audioformat::resample::Resampler;
+use media::container::mp4::writer::Mp4Writer;
+use media::container::{AudioTrack, CodecId, ContainerReader, Frame, Track, VideoTrack};
 use media::pixelformat::{ConvertPixelFormat, PixelFormat, Rgb24};
+use media::playback::recorder::{AudioRecordingConfig, Recorder, VideoRecordingConfig};
 use media::playback::Player;
 use media::videodecoder::{DecodedVideoFrame, VideoDecoder};
+use media::videoencoder::VideoEncoderConfig;
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::{self, Event, WindowEventId};
 use sdl2::keycode::KeyCode;
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
 use sdl2::render::{ACCELERATED, PRESENTVSYNC, RenderDriverIndex, Renderer, RendererParent};
 use sdl2::render::{Texture, TextureAccess};
 use sdl2::video::{OPENGL, RESIZABLE, Window, WindowPos};
@@ -93,19 +98,29 @@ impl ExampleMediaPlayer {
 struct ExampleVideoRenderer<'a> {
     /// The SDL renderer.
     renderer: &'a Renderer,
-    /// The YUV texture we're using.
+    /// The YUV texture we're using, always kept sized to match the window, so `present` can
+    /// blit it unscaled; `upload` is what actually resizes each decoded frame to fit it, via
+    /// `pixelformat`'s own bilinear scaler, rather than leaving it to SDL's blit (which has no
+    /// control over filtering quality).
     texture: Texture<'a>,
+    /// The dimensions `texture` is currently sized at.
+    width: i32,
+    height: i32,
 }
 
 impl<'a> ExampleVideoRenderer<'a> {
-    fn new<'b>(renderer: &'b Renderer, video_format: SdlVideoFormat, video_height: i32)
+    fn new<'b>(renderer: &'b Renderer,
+               video_format: SdlVideoFormat,
+               width: i32,
+               height: i32)
                -> ExampleVideoRenderer<'b> {
         ExampleVideoRenderer {
             renderer: renderer,
             texture: renderer.create_texture(video_format.sdl_pixel_format,
                                              TextureAccess::Streaming,
-                                             (video_format.sdl_width as i32,
-                                              video_height)).unwrap(),
+                                             (width, height)).unwrap(),
+            width: width,
+            height: height,
         }
     }
 
@@ -115,24 +130,32 @@ impl<'a> ExampleVideoRenderer<'a> {
         let video_track = reader.track_by_number(video_track_number as c_long);
         let video_track = video_track.as_video_track().unwrap();
 
-        let rect = if let &RendererParent::Window(ref window) = self.renderer.get_parent() {
-            let (width, height) = window.get_size();
-            Rect::new(0, 0, width, height)
+        let (width, height) = if let &RendererParent::Window(ref window) = self.renderer.get_parent() {
+            window.get_size()
         } else {
             panic!("Renderer parent wasn't a window!")
         };
 
+        if width != self.width || height != self.height {
+            let video_format = SdlVideoFormat::from_video_track(&*video_track);
+            self.texture = self.renderer.create_texture(video_format.sdl_pixel_format,
+                                                         TextureAccess::Streaming,
+                                                         (width, height)).unwrap();
+            self.width = width;
+            self.height = height;
+        }
+
         self.upload(image, &*video_track);
         let mut drawer = self.renderer.drawer();
-        drawer.copy(&self.texture, None, Some(rect));
+        drawer.copy(&self.texture, None, None);
         drawer.present();
     }
 
     fn upload(&mut self, image: Box<DecodedVideoFrame + 'static>, video_track: &VideoTrack) {
+        let (width, height) = (self.width as usize, self.height as usize);
         drop(self.texture.with_lock(None, |pixels, stride| {
             // FIXME(pcwalton): Workaround for rust-sdl2#331: the pixels array may be too small.
             let output_video_format = SdlVideoFormat::from_video_track(video_track);
-            let height = video_track.height() as usize;
             let real_length = match output_video_format.media_pixel_format {
                 PixelFormat::I420 => {
                     stride as usize * height + 2 * ((stride / 2) as usize * (height / 2))
@@ -148,7 +171,7 @@ impl<'a> ExampleVideoRenderer<'a> {
                                  &mut [u8]>(slice::from_raw_mut_buf(&mut pixels.as_mut_ptr(),
                                                                     real_length))
             };
-            upload_image(video_track, &*image, pixels, stride as i32)
+            upload_image(video_track, &*image, pixels, stride as i32, (width, height))
         }));
     }
 }
@@ -156,18 +179,9 @@ impl<'a> ExampleVideoRenderer<'a> {
 /// SDL cannot natively display all pixel formats that `rust-media` supports. Therefore we may have
 /// to do pixel format conversion ourselves. This structure contains the mapping from the pixel
 /// format of the codec to the nearest matching SDL format.
-///
-/// Additionally, SDL is buggy with odd (as in, the opposite of even) video widths in some drivers.
-/// So we have to store an "SDL width" for each video, which may be different from the real video
-/// width. See:
-///
-///     https://trac.ffmpeg.org/attachment/ticket/1322/0001-ffplay-fix-odd-YUV-width-by-cropping-
-///     the-video.patch
-///
 struct SdlVideoFormat {
     media_pixel_format: PixelFormat<'static>,
     sdl_pixel_format: PixelFormatEnum,
-    sdl_width: u16,
 }
 
 impl SdlVideoFormat {
@@ -181,13 +195,21 @@ impl SdlVideoFormat {
         SdlVideoFormat {
             media_pixel_format: media_pixel_format,
             sdl_pixel_format: sdl_pixel_format,
-            sdl_width: video_track.width() & !1,
         }
     }
 }
 
+/// The sinc kernel length and phase count `ExampleAudioRenderer` resamples with; see
+/// `Resampler::new`. Chosen for reasonable quality at modest cost, not tuned for this example.
+const RESAMPLER_TAPS: usize = 32;
+const RESAMPLER_PHASES: usize = 64;
+
 pub struct ExampleAudioRenderer {
     samples: Vec<f32>,
+    /// Converts decoded samples from the stream's native sampling rate to whatever rate the
+    /// device actually negotiated, if `ExampleAudioRenderer::new` found they differ; `None` when
+    /// no conversion is needed.
+    resampler: Option<Resampler>,
 }
 
 impl AudioCallback<f32> for ExampleAudioRenderer {
@@ -214,15 +236,31 @@ impl AudioCallback<f32> for ExampleAudioRenderer {
 
 impl ExampleAudioRenderer {
     pub fn new(sample_rate: f64, channels: u16) -> AudioDevice<ExampleAudioRenderer> {
+        let output_channels = cmp::min(channels, 2) as usize;
         let desired_spec = AudioSpecDesired {
             freq: sample_rate as i32,
-            channels: cmp::min(channels, 2) as u8,
+            channels: output_channels as u8,
             samples: 0,
             callback: ExampleAudioRenderer {
                 samples: Vec::new(),
+                resampler: None,
             },
         };
-        desired_spec.open_audio_device(None, false).unwrap()
+        let mut device = desired_spec.open_audio_device(None, false).unwrap();
+
+        // SDL is free to negotiate a different rate than the one we asked for; resample to
+        // whatever it actually opened the device at, rather than silently playing the stream back
+        // at the wrong speed.
+        let device_rate = device.get_spec().freq as f64;
+        if device_rate != sample_rate {
+            device.lock().resampler = Some(Resampler::new(sample_rate as u32,
+                                                           device_rate as u32,
+                                                           output_channels,
+                                                           RESAMPLER_TAPS,
+                                                           RESAMPLER_PHASES));
+        }
+
+        device
     }
 }
 
@@ -230,31 +268,100 @@ fn enqueue_audio_samples(device: &mut AudioDevice<ExampleAudioRenderer>,
                          input_samples: &[Vec<f32>]) {
     // Gather up all the channels so we can perform audio format conversion.
     let channels = device.get_spec().channels;
-    let input_samples: Vec<_> = input_samples.iter()
-                                             .take(2)
-                                             .map(|samples| samples.as_slice())
-                                             .collect();
+    let input_channels: Vec<_> = input_samples.iter().map(|samples| samples.as_slice()).collect();
+
+    // Downmix (or upmix) to the number of channels the audio device actually wants, instead of
+    // just dropping every channel past the front pair.
+    let output_channels = cmp::min(channels, 2) as usize;
+    let input_sample_count = input_channels[0].len();
+    let source_layout = match input_channels.len() {
+        1 => Some(ChannelLayout::Mono),
+        2 => Some(ChannelLayout::Stereo),
+        6 => Some(ChannelLayout::Surround51),
+        8 => Some(ChannelLayout::Surround71),
+        // An input channel count `ChannelLayout` has no variant for (3, 4, 5, 7, 9+): don't
+        // guess a layout, since matching it against the wrong one here can hand `for_layout` a
+        // channel count that happens to equal `output_channels`, which would silently return an
+        // identity matrix sized for the *guessed* layout rather than the real input.
+        _ => None,
+    };
+    let matrix = source_layout.and_then(|layout| {
+        DownmixMatrix::for_layout(layout, output_channels)
+    }).unwrap_or_else(|| {
+        // An unrecognized channel count: fall back to routing each input channel to the
+        // identically-numbered output channel, and dropping whatever doesn't fit.
+        let inputs = input_channels.len();
+        let mut gains = vec![vec![0.0f32; inputs]; output_channels];
+        for (output_channel, row) in gains.iter_mut().enumerate() {
+            if output_channel < inputs {
+                row[output_channel] = 1.0
+            }
+        }
+        DownmixMatrix::new(gains)
+    });
+    let mut mixed: Vec<Vec<f32>> = (0..output_channels).map(|_| {
+        vec![0.0f32; input_sample_count]
+    }).collect();
+    {
+        let mut mixed_channels: Vec<_> = mixed.iter_mut()
+                                              .map(|channel| channel.as_mut_slice())
+                                              .collect();
+        matrix.apply(input_channels.as_slice(), mixed_channels.as_mut_slice());
+    }
+    let mixed_channels: Vec<_> = mixed.iter().map(|channel| channel.as_slice()).collect();
 
-    // Make room for the samples in the output buffer.
-    let output_channels = cmp::min(channels, 2);
     let mut output = device.lock();
+
+    // Resample from the stream's native rate to whatever rate the device actually negotiated, if
+    // `ExampleAudioRenderer::new` found they differ.
+    let resampled;
+    let (final_channels, final_sample_count): (Vec<&[f32]>, usize) = match output.resampler {
+        Some(ref mut resampler) => {
+            // `Resampler::process` only produces as many frames as fit in `output`, so size it
+            // generously: the true ratio is out_rate/in_rate, but neither rate is available here.
+            // This is an upper bound for ratios up to 4x; at higher ratios, input that doesn't fit
+            // stays pending inside the resampler and is converted on a later call.
+            let max_output_frames = input_sample_count * 4 + RESAMPLER_TAPS;
+            let mut buffers: Vec<Vec<f32>> = (0..output_channels).map(|_| {
+                vec![0.0f32; max_output_frames]
+            }).collect();
+            let produced = {
+                let mut buffer_slices: Vec<_> = buffers.iter_mut()
+                                                       .map(|channel| channel.as_mut_slice())
+                                                       .collect();
+                resampler.process(mixed_channels.as_slice(), buffer_slices.as_mut_slice())
+            };
+            for buffer in buffers.iter_mut() {
+                buffer.truncate(produced);
+            }
+            resampled = buffers;
+            (resampled.iter().map(|channel| channel.as_slice()).collect(), produced)
+        }
+        None => (mixed_channels.clone(), input_sample_count),
+    };
+
+    // Make room for the samples in the output buffer.
     let output_index = output.samples.len();
-    let input_sample_count = input_samples[0].len();
-    let output_length = input_sample_count * output_channels as usize;
+    let output_length = final_sample_count * output_channels;
     output.samples.resize(output_index + output_length, 0.0);
 
     // Perform audio format conversion.
     Float32Planar.convert(&Float32Interleaved,
                           &mut [&mut output.samples[output_index..]],
-                          input_samples.as_slice(),
-                          output_channels as usize).unwrap();
+                          final_channels.as_slice(),
+                          output_channels).unwrap();
 }
 
+/// Converts `image` into `output_pixels`, scaling it from the video track's coded dimensions to
+/// `dest_dims` (the window's current size) along the way, via `PixelFormat::convert_scaled`'s
+/// own bilinear scaler, so a decoded frame can be shown at any window size without relying on
+/// SDL's own (lower-quality, uncontrollable) blit-time stretch.
 fn upload_image(video_track: &VideoTrack,
                 image: &DecodedVideoFrame,
                 output_pixels: &mut [u8],
-                output_stride: i32) {
-    let height = video_track.height();
+                output_stride: i32,
+                dest_dims: (usize, usize)) {
+    let (dest_width, dest_height) = dest_dims;
     let pixel_format = image.pixel_format();
 
     // Gather up all the input pixels and strides so we can do pixel format conversion.
@@ -270,10 +377,10 @@ fn upload_image(video_track: &VideoTrack,
     let (mut output_pixels, output_strides) = match output_video_format.media_pixel_format {
         PixelFormat::I420 => {
             let (output_luma, output_chroma) =
-                output_pixels.split_at_mut(output_stride as usize * height as usize);
+                output_pixels.split_at_mut(output_stride as usize * dest_height);
             let output_chroma_stride = output_stride as usize / 2;
             let (output_u, output_v) =
-                output_chroma.split_at_mut(output_chroma_stride as usize * (height / 2) as usize);
+                output_chroma.split_at_mut(output_chroma_stride * (dest_height / 2));
             (vec![output_luma, output_u, output_v],
              vec![output_stride as usize, output_chroma_stride, output_chroma_stride])
         }
@@ -281,20 +388,63 @@ fn upload_image(video_track: &VideoTrack,
         _ => panic!("SDL can't natively render in {:?}!", output_video_format.media_pixel_format),
     };
 
-    // Perform pixel format conversion.
-    pixel_format.convert(&output_video_format.media_pixel_format,
-                         output_pixels.as_mut_slice(),
-                         output_strides.as_slice(),
-                         input_pixels.as_slice(),
-                         input_strides.as_slice(),
-                         output_video_format.sdl_width as usize,
-                         height as usize).unwrap();
+    // Perform pixel format conversion and scaling together. `convert_scaled` handles odd
+    // dimensions and subsampled chroma planes correctly, so there's no need to crop to an even
+    // width first.
+    let src_dims = (video_track.width() as usize, video_track.height() as usize);
+    pixel_format.convert_scaled(&output_video_format.media_pixel_format,
+                                output_pixels.as_mut_slice(),
+                                output_strides.as_slice(),
+                                (dest_width, dest_height),
+                                input_pixels.as_slice(),
+                                input_strides.as_slice(),
+                                src_dims).unwrap();
+}
+
+/// Builds a `Recorder` that muxes `player`'s tracks to `output_path` as MP4. Picks H.264/AAC as
+/// the recording codecs, since those are what `container::mp4::writer::Mp4Writer` and this
+/// tree's decoders are most likely to be paired with; `Recorder::new` fails if no registered
+/// encoder actually supports them (this tree registers none, so until one is, recording always
+/// fails here and the caller falls back to plain playback).
+fn build_recorder(player: &Player, output_path: &Path) -> Result<Recorder, ()> {
+    let output = try!(File::create(output_path).map_err(|_| ()));
+    let writer: Box<media::container::ContainerWriter> = Box::new(Mp4Writer::new(output));
+
+    let video = player.video_track_number().map(|video_track_number| {
+        let video_track = player.reader.track_by_number(video_track_number as c_long);
+        let video_track = video_track.as_video_track().unwrap();
+        VideoRecordingConfig {
+            encoder_config: VideoEncoderConfig {
+                codec: CodecId::H264,
+                width: video_track.width() as u16,
+                height: video_track.height() as u16,
+            },
+            encoder_pixel_format: PixelFormat::I420,
+            source_pixel_format: video_track.pixel_format(),
+            source_width: video_track.width() as u16,
+            source_height: video_track.height() as u16,
+        }
+    });
+
+    let audio = player.audio_track_number().map(|audio_track_number| {
+        let audio_track = player.reader.track_by_number(audio_track_number as c_long);
+        let audio_track = audio_track.as_audio_track().unwrap();
+        AudioRecordingConfig {
+            encoder_config: AudioEncoderConfig {
+                codec: CodecId::Aac,
+                sample_rate: audio_track.sampling_rate() as u32,
+                channels: audio_track.channels(),
+            },
+        }
+    });
+
+    Recorder::new(writer, video, audio)
 }
 
 fn main() {
     let args: Vec<String> = env::args().map(|arg| arg.into_string().unwrap()).collect();
     if args.len() < 3 {
-        println!("usage: example path-to-video-or-audio-file mime-type");
+        println!("usage: example path-to-video-or-audio-file mime-type [path-to-record-to]");
         return
     }
 
@@ -321,6 +471,7 @@ fn main() {
         let video_format = SdlVideoFormat::from_video_track(&*video_track);
         ExampleVideoRenderer::new(renderer.as_ref().unwrap(),
                                   video_format,
+                                  video_track.width() as i32,
                                   video_track.height() as i32)
     });
 
@@ -333,6 +484,16 @@ fn main() {
         renderer
     });
 
+    let mut recorder = args.get(3).and_then(|output_path| {
+        match build_recorder(&player, &Path::new(output_path.as_slice())) {
+            Ok(recorder) => Some(recorder),
+            Err(_) => {
+                warn!("couldn't set up recording to {}; continuing without it", output_path);
+                None
+            }
+        }
+    });
+
     loop {
         if player.decode_frame().is_err() {
             break
@@ -349,6 +510,13 @@ fn main() {
             Err(_) => break,
         };
 
+        if let Some(ref mut recorder) = recorder {
+            if recorder.record_frame(&frame).is_err() {
+                warn!("error recording a frame; stopping playback");
+                break
+            }
+        }
+
         if let Some(ref mut video_renderer) = video_renderer {
             video_renderer.present(frame.video_frame.unwrap(), &mut player);
         }
@@ -360,5 +528,11 @@ fn main() {
             break
         }
     }
+
+    if let Some(recorder) = recorder {
+        if recorder.finish().is_err() {
+            warn!("error finishing the recording");
+        }
+    }
 }
 
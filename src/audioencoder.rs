@@ -0,0 +1,84 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encoding of raw decoded audio samples into coded audio packets: the audio counterpart of
+//! `videoencoder`.
+
+use container::CodecId;
+
+/// A single coded packet produced by an `AudioEncoder`, ready to be handed to
+/// `container::ContainerWriter::write_sample`.
+pub struct EncodedPacket {
+    /// The coded payload, exactly as it should appear in the output file.
+    pub data: Vec<u8>,
+    /// This packet's duration, in the track's timescale units (for audio, sample count, since an
+    /// audio track's timescale is its sample rate).
+    pub duration: u32,
+    /// Whether this packet is usable as a random-access point. Always `true` for audio: unlike
+    /// video, every audio packet is independently decodable.
+    pub sync: bool,
+}
+
+/// An audio encoder for a single codec (AAC, Opus, ...).
+///
+/// Implementations are registered by codec id and instantiated via `create_audio_encoder` as a
+/// recording pipeline (see `playback::recorder::Recorder`) sets up its output tracks.
+pub trait AudioEncoder {
+    /// Encodes one block of planar audio samples, one slice per channel, returning the coded
+    /// packet if one is ready.
+    ///
+    /// Not every call necessarily returns a packet immediately: some encoders buffer samples
+    /// internally until they have a full frame's worth to encode, in which case this returns
+    /// `Ok(None)` until one is; see also `flush`, for packets still buffered at end of stream.
+    fn encode(&mut self, samples: &[&[f32]]) -> Result<Option<EncodedPacket>, ()>;
+
+    /// Flushes one buffered packet, if any, from an encoder that doesn't emit its packets
+    /// immediately. Call this repeatedly at end of stream until it returns `Ok(None)`.
+    fn flush(&mut self) -> Result<Option<EncodedPacket>, ()> {
+        Ok(None)
+    }
+
+    /// The codec-specific decoder configuration record (an `esds` elementary stream descriptor,
+    /// or a `dOps` payload, depending on codec) describing how to decode the packets this encoder
+    /// produces. Must be available as soon as the encoder is constructed, since muxers need it to
+    /// write a track's sample description before any samples arrive.
+    fn decoder_configuration(&self) -> Vec<u8>;
+}
+
+/// Parameters needed to construct an `AudioEncoder`.
+pub struct AudioEncoderConfig {
+    /// The codec to encode to.
+    pub codec: CodecId,
+    /// The number of samples per second the encoder should expect.
+    pub sample_rate: u32,
+    /// The number of channels the encoder should expect, each passed to `encode` as its own
+    /// planar slice.
+    pub channels: u16,
+}
+
+/// A constructor that attempts to build an `AudioEncoder` for the given configuration, returning
+/// `None` if this implementation doesn't support the requested codec.
+pub type AudioEncoderConstructor = fn(&AudioEncoderConfig) -> Option<Box<AudioEncoder + 'static>>;
+
+/// The audio encoders `rust-media` knows how to construct, tried in order by
+/// `create_audio_encoder`. Concrete encoders push their constructor onto this list; none are
+/// registered yet, since this tree only implements the encoding *interface* and the MP4 muxer it
+/// feeds.
+pub static REGISTERED_AUDIO_ENCODERS: &'static [AudioEncoderConstructor] = &[];
+
+/// Tries each registered audio encoder in turn, returning the first one that claims to support
+/// `config.codec`.
+pub fn create_audio_encoder(config: &AudioEncoderConfig) -> Option<Box<AudioEncoder + 'static>> {
+    for constructor in REGISTERED_AUDIO_ENCODERS.iter() {
+        if let Some(encoder) = constructor(config) {
+            return Some(encoder)
+        }
+    }
+    None
+}
@@ -0,0 +1,84 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Audio sample formats and conversions between them.
+
+pub mod channels;
+pub mod resample;
+
+/// A marker type for planar `f32` samples: one contiguous buffer per channel.
+pub struct Float32Planar;
+
+/// A marker type for interleaved `f32` samples: channels interleaved within a single buffer.
+pub struct Float32Interleaved;
+
+/// Conversion between audio sample layouts (planar vs. interleaved).
+///
+/// Like `ConvertPixelFormat`, `self` names the source layout and `dest_format` names the
+/// destination layout; `channels` gives the channel count, which must agree between the two.
+pub trait ConvertAudioFormat {
+    /// Converts `src_samples` (in `self`'s layout) into `dest_samples` (in `dest_format`'s
+    /// layout). For a planar format, each element of the outer slice is one channel's buffer;
+    /// for an interleaved format, there is exactly one buffer holding all channels.
+    fn convert(&self,
+               dest_format: &AudioFormat,
+               dest_samples: &mut [&mut [f32]],
+               src_samples: &[&[f32]],
+               channels: usize)
+               -> Result<(), ()>;
+}
+
+/// A tag trait implemented by the marker types in this module, so that `ConvertAudioFormat` can
+/// take `dest_format` as a trait object without naming a concrete destination type.
+pub trait AudioFormat {}
+
+impl AudioFormat for Float32Planar {}
+impl AudioFormat for Float32Interleaved {}
+
+impl ConvertAudioFormat for Float32Planar {
+    fn convert(&self,
+               _: &AudioFormat,
+               dest_samples: &mut [&mut [f32]],
+               src_samples: &[&[f32]],
+               channels: usize)
+               -> Result<(), ()> {
+        if src_samples.len() < channels || dest_samples.len() != 1 {
+            return Err(())
+        }
+        let frames = src_samples[0].len();
+        let dest = &mut dest_samples[0];
+        for frame in 0..frames {
+            for channel in 0..channels {
+                dest[frame * channels + channel] = src_samples[channel][frame]
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConvertAudioFormat for Float32Interleaved {
+    fn convert(&self,
+               _: &AudioFormat,
+               dest_samples: &mut [&mut [f32]],
+               src_samples: &[&[f32]],
+               channels: usize)
+               -> Result<(), ()> {
+        if src_samples.len() != 1 || dest_samples.len() < channels {
+            return Err(())
+        }
+        let src = src_samples[0];
+        let frames = src.len() / channels;
+        for frame in 0..frames {
+            for channel in 0..channels {
+                dest_samples[channel][frame] = src[frame * channels + channel]
+            }
+        }
+        Ok(())
+    }
+}
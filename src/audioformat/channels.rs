@@ -0,0 +1,196 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Channel remapping: downmixing (5.1 -> stereo, ...), upmixing, and arbitrary channel routing,
+//! driven by a gain matrix rather than simply dropping the channels a caller didn't ask for.
+
+/// A standard multichannel speaker layout, in the channel order `rust-media`'s decoders present
+/// their planar output in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelLayout {
+    /// A single channel.
+    Mono,
+    /// Front left, front right.
+    Stereo,
+    /// Front left, front right, center, LFE, surround left, surround right.
+    Surround51,
+    /// Front left, front right, center, LFE, surround left, surround right, rear left, rear
+    /// right.
+    Surround71,
+}
+
+impl ChannelLayout {
+    /// The number of channels this layout describes.
+    pub fn channel_count(&self) -> usize {
+        match *self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+            ChannelLayout::Surround71 => 8,
+        }
+    }
+}
+
+/// A `[output channel][input channel]` gain matrix: `output[o] = sum(gains[o][i] * input[i])`.
+///
+/// Downmixing (6 input channels to 2, say) and upmixing (2 to 6) are both just matrices of the
+/// appropriate shape; so is "extract one channel", via a matrix with a single `1.0` in the row
+/// for the channel of interest and zeroes elsewhere.
+pub struct DownmixMatrix {
+    /// `gains[o]` gives the per-input-channel weights for output channel `o`.
+    gains: Vec<Vec<f32>>,
+}
+
+impl DownmixMatrix {
+    /// Builds a matrix directly from caller-supplied gains, for arbitrary remappings (extracting
+    /// a single channel, routing channels to unconventional outputs, and so on).
+    ///
+    /// `gains.len()` is the number of output channels; every row must be the same length, the
+    /// number of input channels.
+    pub fn new(gains: Vec<Vec<f32>>) -> DownmixMatrix {
+        DownmixMatrix {
+            gains: gains,
+        }
+    }
+
+    /// Builds the standard ITU-R BS.775 downmix matrix from `source` to `target_channels`
+    /// channels, if `rust-media` knows one. Currently this covers downmixing `Surround51` and
+    /// `Surround71` to `Stereo` or `Mono`, `Stereo` to `Mono`, copying `Mono` to any number of
+    /// channels, and the identity mapping when no remapping is needed.
+    pub fn for_layout(source: ChannelLayout, target_channels: usize) -> Option<DownmixMatrix> {
+        if target_channels == source.channel_count() {
+            return Some(DownmixMatrix::identity(target_channels))
+        }
+
+        const FL: usize = 0;
+        const FR: usize = 1;
+        const C: usize = 2;
+        const LFE: usize = 3;
+        const SL: usize = 4;
+        const SR: usize = 5;
+        const RL: usize = 6;
+        const RR: usize = 7;
+
+        // The ITU-R BS.775 center/surround downmix coefficient: -3 dB, i.e. 1/sqrt(2).
+        const MIX: f32 = 0.707;
+
+        match (source, target_channels) {
+            (ChannelLayout::Surround51, 2) | (ChannelLayout::Surround71, 2) => {
+                let inputs = source.channel_count();
+                let mut left = vec![0.0; inputs];
+                let mut right = vec![0.0; inputs];
+                left[FL] = 1.0;
+                left[C] = MIX;
+                left[SL] = MIX;
+                right[FR] = 1.0;
+                right[C] = MIX;
+                right[SR] = MIX;
+                if inputs > RL {
+                    left[RL] = MIX;
+                    right[RR] = MIX;
+                }
+                // LFE (index 3) is intentionally left at a zero gain: attenuating it to silence,
+                // rather than mixing it into the front pair, matches the usual home-theater
+                // downmix convention.
+                Some(DownmixMatrix::new(vec![left, right]))
+            }
+            (ChannelLayout::Surround51, 1) | (ChannelLayout::Surround71, 1) => {
+                DownmixMatrix::for_layout(source, 2).map(|stereo| {
+                    let mono: Vec<f32> = stereo.gains[0].iter()
+                                                        .zip(stereo.gains[1].iter())
+                                                        .map(|(l, r)| (l + r) * 0.5)
+                                                        .collect();
+                    DownmixMatrix::new(vec![mono])
+                })
+            }
+            (ChannelLayout::Stereo, 1) => {
+                Some(DownmixMatrix::new(vec![vec![0.5, 0.5]]))
+            }
+            (ChannelLayout::Mono, target) => {
+                Some(DownmixMatrix::new(vec![vec![1.0]; target]))
+            }
+            _ => None,
+        }
+    }
+
+    /// The matrix that routes each input channel straight to the identically-numbered output
+    /// channel, unchanged.
+    pub fn identity(channels: usize) -> DownmixMatrix {
+        let mut gains = vec![vec![0.0; channels]; channels];
+        for (i, row) in gains.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        DownmixMatrix::new(gains)
+    }
+
+    /// The number of input channels this matrix expects.
+    pub fn input_channels(&self) -> usize {
+        self.gains.first().map_or(0, |row| row.len())
+    }
+
+    /// The number of output channels this matrix produces.
+    pub fn output_channels(&self) -> usize {
+        self.gains.len()
+    }
+
+    /// Applies this matrix to planar `input`, writing the mixed result into planar `output`.
+    /// `input` must have `input_channels()` entries and `output` must have `output_channels()`
+    /// entries; every channel (on both sides) must be the same length.
+    pub fn apply(&self, input: &[&[f32]], output: &mut [&mut [f32]]) {
+        assert_eq!(input.len(), self.input_channels());
+        assert_eq!(output.len(), self.output_channels());
+
+        for (row, out_channel) in self.gains.iter().zip(output.iter_mut()) {
+            let frames = out_channel.len();
+            for frame in 0..frames {
+                let mut accumulator = 0.0f32;
+                for (gain, in_channel) in row.iter().zip(input.iter()) {
+                    if *gain != 0.0 {
+                        accumulator += gain * in_channel[frame];
+                    }
+                }
+                out_channel[frame] = accumulator;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelLayout, DownmixMatrix};
+
+    #[test]
+    fn surround51_to_stereo_matches_hand_computed_bs775_gains() {
+        let matrix = DownmixMatrix::for_layout(ChannelLayout::Surround51, 2).unwrap();
+
+        // One frame per input channel (FL, FR, C, LFE, SL, SR), each exciting only that channel,
+        // so each column of the mixed output is exactly that channel's gain into left/right.
+        let fl = [1.0f32, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let fr = [0.0f32, 1.0, 0.0, 0.0, 0.0, 0.0];
+        let center = [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0];
+        let lfe = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let sl = [0.0f32, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let sr = [0.0f32, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let input: [&[f32]; 6] = [&fl, &fr, &center, &lfe, &sl, &sr];
+
+        let mut left = [0.0f32; 6];
+        let mut right = [0.0f32; 6];
+        {
+            let mut output: [&mut [f32]; 2] = [&mut left, &mut right];
+            matrix.apply(&input, &mut output);
+        }
+
+        assert_eq!((left[0], right[0]), (1.0, 0.0), "FL should route to left only");
+        assert_eq!((left[1], right[1]), (0.0, 1.0), "FR should route to right only");
+        assert_eq!((left[2], right[2]), (0.707, 0.707), "C should split -3dB to both");
+        assert_eq!((left[3], right[3]), (0.0, 0.0), "LFE should be dropped");
+        assert_eq!((left[4], right[4]), (0.707, 0.0), "SL should mix -3dB into left only");
+        assert_eq!((left[5], right[5]), (0.0, 0.707), "SR should mix -3dB into right only");
+    }
+}
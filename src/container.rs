@@ -0,0 +1,221 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Demultiplexing of audio/video container formats (MP4, Ogg, ...) into individual tracks and
+//! frames, and (for formats that support it) multiplexing of tracks back into a container.
+
+use std::old_io::Reader;
+
+use libc::c_long;
+
+use pixelformat::PixelFormat;
+use videodecoder::DecodedVideoFrame;
+
+pub mod mp4;
+
+/// A constructor that attempts to build a `ContainerReader` for the given data stream, returning
+/// `None` if the stream isn't in the format that constructor understands.
+pub type ContainerReaderConstructor =
+    fn(Box<Reader + 'static>, &str) -> Option<Box<ContainerReader + 'static>>;
+
+/// The container readers `rust-media` knows how to construct, tried in order by `create_reader`.
+/// Concrete demuxers (`container::mp4`, and others as they're added) push their constructor onto
+/// this list; none are registered yet, since this tree only implements the ISOBMFF *writer*.
+pub static REGISTERED_CONTAINER_READERS: &'static [ContainerReaderConstructor] = &[];
+
+/// Tries each registered container reader in turn, returning the first one that claims to
+/// understand `mime_type`.
+pub fn create_reader(data: Box<Reader + 'static>, mime_type: &str)
+                      -> Option<Box<ContainerReader + 'static>> {
+    for constructor in REGISTERED_CONTAINER_READERS.iter() {
+        if let Some(reader) = constructor(data, mime_type) {
+            return Some(reader)
+        }
+    }
+    None
+}
+
+/// A container format that can be read from, producing tracks and, frame by frame, the samples
+/// that belong to them.
+///
+/// Implementors are registered with the crate via `REGISTERED_CONTAINER_READERS` and are probed
+/// in order until one claims to recognize the stream.
+pub trait ContainerReader {
+    /// Returns the number of tracks present in this container.
+    fn track_count(&self) -> usize;
+    /// Returns the track with the given zero-based index.
+    fn track_by_index(&self, index: usize) -> Box<Track>;
+    /// Returns the track with the given container-assigned track number.
+    fn track_by_number(&self, number: c_long) -> Box<Track>;
+    /// Decodes and returns the next frame of data, demultiplexed into its constituent per-track
+    /// samples. Frames are returned in decode order, which for the video track may not be the
+    /// same as presentation order; see `playback::Player` and `videodecoder::reorder`. Returns
+    /// `Err(())` on end of stream or unrecoverable error.
+    fn next_frame(&mut self) -> Result<Frame, ()>;
+
+    /// The maximum number of video frames this container's video decoder may emit out of
+    /// presentation order, if it has a video track (i.e. the deepest B-frame reference distance
+    /// its video codec can use; see `videodecoder::reorder::ReorderBuffer`, which
+    /// `playback::Player` sizes from this value). A reader decodes its own video track
+    /// internally, so it's the one in a position to know this, not the codec-agnostic
+    /// `videodecoder::VideoDecoder` trait. Readers with no video track, or whose video codec
+    /// never reorders, can leave this at the default of zero.
+    fn video_reorder_depth(&self) -> usize {
+        0
+    }
+}
+
+/// A single track (elementary stream) within a container.
+pub trait Track {
+    /// This track's container-assigned track number.
+    fn number(&self) -> c_long;
+    /// Downcasts this track to a `VideoTrack`, if it is one.
+    fn as_video_track(&self) -> Option<&VideoTrack>;
+    /// Downcasts this track to an `AudioTrack`, if it is one.
+    fn as_audio_track(&self) -> Option<&AudioTrack>;
+}
+
+/// A video track: a `Track` that carries coded picture data.
+pub trait VideoTrack : Track {
+    /// The coded width of the video, in pixels.
+    fn width(&self) -> i32;
+    /// The coded height of the video, in pixels.
+    fn height(&self) -> i32;
+    /// The pixel format that decoded frames from this track will be presented in.
+    fn pixel_format(&self) -> PixelFormat<'static>;
+    /// The number of ticks that make up one second of this track's presentation timestamps; see
+    /// `DecodedVideoFrame::presentation_timestamp` and `playback::Timestamp`.
+    fn timescale(&self) -> u32;
+}
+
+/// An audio track: a `Track` that carries coded audio samples.
+pub trait AudioTrack : Track {
+    /// The number of samples played per second.
+    fn sampling_rate(&self) -> f64;
+    /// The number of interleaved channels present in the decoded output.
+    fn channels(&self) -> u16;
+}
+
+/// One frame's worth of decoded data, as produced by `ContainerReader::next_frame`.
+pub struct Frame {
+    /// The decoded video frame, if the container has a video track and this frame carries video
+    /// data.
+    pub video_frame: Option<Box<DecodedVideoFrame + 'static>>,
+    /// Decoded planar audio samples for this frame, one `Vec` per channel, if the container has
+    /// an audio track and this frame carries audio data.
+    pub audio_samples: Option<Vec<Vec<f32>>>,
+}
+
+/// Identifies one of the tracks previously added to a `ContainerWriter` via `add_video_track` or
+/// `add_audio_track`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WriterTrackId(pub u32);
+
+/// The codec that a track being written is carrying, along with the codec-specific parameters
+/// that must end up in the sample description box for decoders to make sense of the stream.
+#[derive(Clone, Debug)]
+pub enum SampleCodec {
+    /// AVC/H.264, described by its `avcC` decoder configuration record.
+    H264 { decoder_configuration: Vec<u8> },
+    /// HEVC/H.265, described by its `hvcC` decoder configuration record.
+    H265 { decoder_configuration: Vec<u8> },
+    /// VP9, described by its `vpcC` codec configuration box payload.
+    Vp9 { decoder_configuration: Vec<u8> },
+    /// AAC, described by its `esds` elementary stream descriptor.
+    Aac { decoder_configuration: Vec<u8> },
+    /// Opus, described by its `dOps` box payload.
+    Opus { decoder_configuration: Vec<u8> },
+}
+
+/// A codec identity, independent of any particular decoder configuration payload. Used to select
+/// an encoder implementation by codec, via `videoencoder`'s and `audioencoder`'s codec
+/// registries.
+#[derive(Clone, Copy)]
+pub enum CodecId {
+    /// AVC/H.264.
+    H264,
+    /// HEVC/H.265.
+    H265,
+    /// VP9.
+    Vp9,
+    /// AAC.
+    Aac,
+    /// Opus.
+    Opus,
+}
+
+impl CodecId {
+    /// Builds the `SampleCodec` this codec id corresponds to, carrying the given codec-specific
+    /// decoder configuration payload (an encoder's `decoder_configuration()`, typically).
+    pub fn sample_codec(&self, decoder_configuration: Vec<u8>) -> SampleCodec {
+        match *self {
+            CodecId::H264 => SampleCodec::H264 { decoder_configuration: decoder_configuration },
+            CodecId::H265 => SampleCodec::H265 { decoder_configuration: decoder_configuration },
+            CodecId::Vp9 => SampleCodec::Vp9 { decoder_configuration: decoder_configuration },
+            CodecId::Aac => SampleCodec::Aac { decoder_configuration: decoder_configuration },
+            CodecId::Opus => SampleCodec::Opus { decoder_configuration: decoder_configuration },
+        }
+    }
+}
+
+/// Parameters needed to describe a video track being written, mirroring the subset of
+/// `VideoTrack` that a muxer needs to know up front.
+#[derive(Clone)]
+pub struct VideoTrackConfig {
+    /// The coded width of the video, in pixels.
+    pub width: u16,
+    /// The coded height of the video, in pixels.
+    pub height: u16,
+    /// The number of timescale units that make up one second for this track.
+    pub timescale: u32,
+    /// The codec samples on this track are encoded with.
+    pub codec: SampleCodec,
+}
+
+/// Parameters needed to describe an audio track being written, mirroring the subset of
+/// `AudioTrack` that a muxer needs to know up front.
+#[derive(Clone)]
+pub struct AudioTrackConfig {
+    /// The number of samples played per second.
+    pub sample_rate: u32,
+    /// The number of interleaved channels.
+    pub channels: u16,
+    /// The codec samples on this track are encoded with.
+    pub codec: SampleCodec,
+}
+
+/// A single coded sample (one video frame, or one block of audio) handed to a `ContainerWriter`.
+pub struct Sample<'a> {
+    /// The coded payload, exactly as it should appear in the output file.
+    pub data: &'a [u8],
+    /// This sample's duration, in the track's timescale units.
+    pub duration: u32,
+    /// Whether this sample is usable as a random-access point (for video, an IDR/key frame; all
+    /// audio samples are sync samples).
+    pub sync: bool,
+}
+
+/// A container format that tracks and samples can be written out to, the write-side counterpart
+/// of `ContainerReader`.
+///
+/// Tracks must be added with `add_video_track`/`add_audio_track` before any samples are written,
+/// and `finish` must be called exactly once, after the last sample, to flush any data that can
+/// only be written once every sample's size and position is known (the sample tables, for a
+/// finalized, non-fragmented file).
+pub trait ContainerWriter {
+    /// Registers a new video track and returns the id samples for it should be written under.
+    fn add_video_track(&mut self, config: VideoTrackConfig) -> WriterTrackId;
+    /// Registers a new audio track and returns the id samples for it should be written under.
+    fn add_audio_track(&mut self, config: AudioTrackConfig) -> WriterTrackId;
+    /// Appends one coded sample to the given track, in presentation... well, in the case of
+    /// formats (like this one) that require samples in decode order, decode order.
+    fn write_sample(&mut self, track: WriterTrackId, sample: Sample) -> Result<(), ()>;
+    /// Flushes any buffered data and finalizes the output so that it is a valid, playable file.
+    fn finish(&mut self) -> Result<(), ()>;
+}
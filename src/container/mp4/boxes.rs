@@ -0,0 +1,65 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A small helper for writing ISOBMFF boxes to anything seekable: reserves the 4-byte size
+//! field up front, runs the caller's closure to write the box body, then seeks back and
+//! backpatches the size once the body's length is known.
+
+use std::old_io::{IoResult, Seek, SeekStyle, Writer};
+
+/// Wraps a `Writer + Seek` and provides `write_box`/`write_full_box`.
+pub struct BoxWriter<'a, W: 'a> {
+    writer: &'a mut W,
+}
+
+impl<'a, W: Writer + Seek> BoxWriter<'a, W> {
+    /// Creates a new `BoxWriter` around the given sink.
+    pub fn new(writer: &'a mut W) -> BoxWriter<'a, W> {
+        BoxWriter {
+            writer: writer,
+        }
+    }
+
+    /// Writes a box with the given four-character code. `body` is called with a `BoxWriter`
+    /// that writes into the box's content area; once it returns, this box's size field (which
+    /// was reserved, not yet known, before `body` ran) is backpatched.
+    pub fn write_box<F>(&mut self, fourcc: &[u8; 4], body: F) -> IoResult<()>
+        where F: FnOnce(&mut BoxWriter<W>) -> IoResult<()> {
+        let size_offset = try!(self.writer.tell());
+        try!(self.writer.write_be_u32(0));
+        try!(self.writer.write_all(fourcc));
+        try!(body(self));
+        let end_offset = try!(self.writer.tell());
+        let size = end_offset - size_offset;
+        try!(self.writer.seek(size_offset as i64, SeekStyle::SeekSet));
+        try!(self.writer.write_be_u32(size as u32));
+        try!(self.writer.seek(end_offset as i64, SeekStyle::SeekSet));
+        Ok(())
+    }
+
+    /// Like `write_box`, but for a "full box": one that additionally carries a version byte and
+    /// 24 bits of flags immediately after the four-character code.
+    pub fn write_full_box<F>(&mut self, fourcc: &[u8; 4], version: u8, flags: u32, body: F)
+                              -> IoResult<()>
+        where F: FnOnce(&mut BoxWriter<W>) -> IoResult<()> {
+        self.write_box(fourcc, |writer| {
+            try!(writer.writer.write_u8(version));
+            try!(writer.writer.write_all(&[((flags >> 16) & 0xff) as u8,
+                                            ((flags >> 8) & 0xff) as u8,
+                                            (flags & 0xff) as u8]));
+            body(writer)
+        })
+    }
+
+    /// Gives direct access to the underlying sink, for writing raw (non-box) bytes into the
+    /// current box body.
+    pub fn writer(&mut self) -> &mut W {
+        self.writer
+    }
+}
@@ -0,0 +1,499 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fragmented MP4 (CMAF-compatible) output, for streaming scenarios where `mp4::writer::Mp4Writer`
+//! doesn't fit: that writer needs to buffer every sample in memory and seek back to patch the
+//! sample tables once the whole stream has been seen, which is unusable for a live source with
+//! no defined end.
+//!
+//! `FragmentedMp4Writer` instead writes an initialization segment once (`ftyp` + `moov`, with
+//! empty sample tables and an `mvex`/`trex` default-sample description in place of them),
+//! followed by any number of self-contained media fragments (`moof` + `mdat`), each of which can
+//! be written out and handed to a segmenter or HTTP response as soon as it's flushed, with no
+//! further seeking required.
+
+use std::old_io::{IoResult, Seek, Writer};
+
+use container::{AudioTrackConfig, ContainerWriter, Sample, SampleCodec, VideoTrackConfig,
+                 WriterTrackId};
+use container::mp4::boxes::BoxWriter;
+use container::mp4::writer::{write_audio_sample_entry, write_visual_sample_entry};
+
+enum TrackConfig {
+    Video(VideoTrackConfig),
+    Audio(AudioTrackConfig),
+}
+
+impl TrackConfig {
+    fn timescale(&self) -> u32 {
+        match *self {
+            TrackConfig::Video(ref config) => config.timescale,
+            TrackConfig::Audio(ref config) => config.sample_rate,
+        }
+    }
+
+    fn codec(&self) -> &SampleCodec {
+        match *self {
+            TrackConfig::Video(ref config) => &config.codec,
+            TrackConfig::Audio(ref config) => &config.codec,
+        }
+    }
+}
+
+struct PendingSample {
+    data: Vec<u8>,
+    duration: u32,
+    sync: bool,
+}
+
+struct FragTrackState {
+    config: TrackConfig,
+    pending: Vec<PendingSample>,
+    fragment_duration: u32,
+    /// Sum of every flushed fragment's duration so far, in this track's timescale: the
+    /// presentation time the *next* fragment starts at, and hence its `tfdt`'s `base_time`.
+    total_duration: u64,
+}
+
+/// Writes tracks out as an initialization segment followed by a stream of CMAF-compatible media
+/// fragments, suitable for HLS/DASH.
+///
+/// Call `add_video_track`/`add_audio_track` to describe the tracks, then `write_sample` for each
+/// coded sample; a fragment is flushed automatically once it has accumulated `flush_interval_ms`
+/// worth of samples on any track, or as soon as a video keyframe arrives after the first one
+/// (whichever comes first). Without a flush interval, every fragment after the first one starts
+/// on a video sync sample; a flush triggered by the interval can start a fragment on a non-sync
+/// sample. Call
+/// `flush_fragment` directly to force a flush (for example, at a forced keyframe), and `finish`
+/// at end of stream to flush anything left over.
+pub struct FragmentedMp4Writer<W> {
+    output: W,
+    tracks: Vec<FragTrackState>,
+    sequence_number: u32,
+    wrote_init_segment: bool,
+    flush_interval_ms: Option<u32>,
+}
+
+impl<W: Writer + Seek> FragmentedMp4Writer<W> {
+    /// Creates a new writer. `flush_interval_ms`, if given, additionally flushes a fragment once
+    /// that much media time has accumulated, even absent a keyframe (useful for audio-only
+    /// streams, which have no keyframes to key off of).
+    pub fn new(output: W, flush_interval_ms: Option<u32>) -> FragmentedMp4Writer<W> {
+        FragmentedMp4Writer {
+            output: output,
+            tracks: Vec::new(),
+            sequence_number: 0,
+            wrote_init_segment: false,
+            flush_interval_ms: flush_interval_ms,
+        }
+    }
+
+    fn brands(&self) -> (&'static [u8; 4], Vec<&'static [u8; 4]>) {
+        let mut compatible: Vec<&'static [u8; 4]> = vec![b"iso5", b"iso6", b"mp42", b"cmfc"];
+        for track in self.tracks.iter() {
+            match *track.config.codec() {
+                SampleCodec::H264 { .. } => compatible.push(b"avc1"),
+                SampleCodec::H265 { .. } => compatible.push(b"hev1"),
+                SampleCodec::Vp9 { .. } => compatible.push(b"vp09"),
+                SampleCodec::Aac { .. } | SampleCodec::Opus { .. } => {}
+            }
+        }
+        (b"iso5", compatible)
+    }
+
+    /// Writes the `ftyp` + `moov` initialization segment. Must be called (via `write_sample`,
+    /// which calls it lazily on the first sample) before any fragment is written.
+    fn write_init_segment(&mut self) -> IoResult<()> {
+        let (major_brand, compatible_brands) = self.brands();
+        let mut writer = BoxWriter::new(&mut self.output);
+        try!(writer.write_box(b"ftyp", |writer| {
+            try!(writer.writer().write_all(major_brand));
+            try!(writer.writer().write_be_u32(0));
+            for brand in compatible_brands.iter() {
+                try!(writer.writer().write_all(*brand));
+            }
+            Ok(())
+        }));
+
+        writer.write_box(b"moov", |writer| {
+            try!(writer.write_full_box(b"mvhd", 0, 0, |writer| {
+                try!(writer.writer().write_be_u32(0));
+                try!(writer.writer().write_be_u32(0));
+                try!(writer.writer().write_be_u32(1000)); // movie timescale
+                try!(writer.writer().write_be_u32(0)); // duration: unknown/live
+                try!(writer.writer().write_be_u32(0x00010000));
+                try!(writer.writer().write_be_u16(0x0100));
+                try!(writer.writer().write_be_u16(0));
+                try!(writer.writer().write_be_u64(0));
+                for value in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000].iter() {
+                    try!(writer.writer().write_be_u32(*value));
+                }
+                for _ in 0..6 {
+                    try!(writer.writer().write_be_u32(0));
+                }
+                writer.writer().write_be_u32(self.tracks.len() as u32 + 1)
+            }));
+
+            for (index, track) in self.tracks.iter().enumerate() {
+                let track_id = index as u32 + 1;
+                try!(writer.write_box(b"trak", |writer| {
+                    try!(writer.write_full_box(b"tkhd", 0, 0x000007, |writer| {
+                        try!(writer.writer().write_be_u32(0));
+                        try!(writer.writer().write_be_u32(0));
+                        try!(writer.writer().write_be_u32(track_id));
+                        try!(writer.writer().write_be_u32(0));
+                        try!(writer.writer().write_be_u32(0)); // duration: unknown/live
+                        try!(writer.writer().write_be_u64(0));
+                        try!(writer.writer().write_be_u16(0));
+                        try!(writer.writer().write_be_u16(0));
+                        let is_audio = match track.config { TrackConfig::Audio(_) => true, _ => false };
+                        try!(writer.writer().write_be_u16(if is_audio { 0x0100 } else { 0 }));
+                        try!(writer.writer().write_be_u16(0));
+                        for value in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000].iter() {
+                            try!(writer.writer().write_be_u32(*value));
+                        }
+                        let (width, height) = match track.config {
+                            TrackConfig::Video(ref config) => (config.width, config.height),
+                            TrackConfig::Audio(_) => (0, 0),
+                        };
+                        try!(writer.writer().write_be_u32((width as u32) << 16));
+                        writer.writer().write_be_u32((height as u32) << 16)
+                    }));
+
+                    writer.write_box(b"mdia", |writer| {
+                        try!(writer.write_full_box(b"mdhd", 0, 0, |writer| {
+                            try!(writer.writer().write_be_u32(0));
+                            try!(writer.writer().write_be_u32(0));
+                            try!(writer.writer().write_be_u32(track.config.timescale()));
+                            try!(writer.writer().write_be_u32(0)); // duration: unknown/live
+                            try!(writer.writer().write_be_u16(0x55c4));
+                            writer.writer().write_be_u16(0)
+                        }));
+
+                        let (handler_type, handler_name): (&[u8; 4], &[u8]) = match track.config {
+                            TrackConfig::Video(_) => (b"vide", b"VideoHandler\0"),
+                            TrackConfig::Audio(_) => (b"soun", b"SoundHandler\0"),
+                        };
+                        try!(writer.write_full_box(b"hdlr", 0, 0, |writer| {
+                            try!(writer.writer().write_be_u32(0));
+                            try!(writer.writer().write_all(handler_type));
+                            try!(writer.writer().write_be_u64(0));
+                            try!(writer.writer().write_be_u32(0));
+                            writer.writer().write_all(handler_name)
+                        }));
+
+                        writer.write_box(b"minf", |writer| {
+                            match track.config {
+                                TrackConfig::Video(_) => {
+                                    try!(writer.write_full_box(b"vmhd", 0, 1, |writer| {
+                                        try!(writer.writer().write_be_u16(0));
+                                        writer.writer().write_be_u64(0)
+                                    }));
+                                }
+                                TrackConfig::Audio(_) => {
+                                    try!(writer.write_full_box(b"smhd", 0, 0, |writer| {
+                                        try!(writer.writer().write_be_u16(0));
+                                        writer.writer().write_be_u16(0)
+                                    }));
+                                }
+                            }
+
+                            try!(writer.write_box(b"dinf", |writer| {
+                                writer.write_full_box(b"dref", 0, 0, |writer| {
+                                    try!(writer.writer().write_be_u32(1));
+                                    writer.write_full_box(b"url ", 0, 1, |_| Ok(()))
+                                })
+                            }));
+
+                            // The sample tables themselves are intentionally empty: every sample
+                            // in a fragmented file lives in a `moof`/`traf`, not here.
+                            writer.write_box(b"stbl", |writer| {
+                                try!(writer.write_full_box(b"stsd", 0, 0, |writer| {
+                                    try!(writer.writer().write_be_u32(1));
+                                    match track.config {
+                                        TrackConfig::Video(ref config) =>
+                                            write_visual_sample_entry(writer, config),
+                                        TrackConfig::Audio(ref config) =>
+                                            write_audio_sample_entry(writer, config),
+                                    }
+                                }));
+                                try!(writer.write_full_box(b"stts", 0, 0, |writer| writer.writer().write_be_u32(0)));
+                                try!(writer.write_full_box(b"stsc", 0, 0, |writer| writer.writer().write_be_u32(0)));
+                                try!(writer.write_full_box(b"stsz", 0, 0, |writer| {
+                                    try!(writer.writer().write_be_u32(0));
+                                    writer.writer().write_be_u32(0)
+                                }));
+                                writer.write_full_box(b"stco", 0, 0, |writer| writer.writer().write_be_u32(0))
+                            })
+                        })
+                    })
+                }));
+            }
+
+            writer.write_box(b"mvex", |writer| {
+                for (index, track) in self.tracks.iter().enumerate() {
+                    let track_id = index as u32 + 1;
+                    try!(writer.write_full_box(b"trex", 0, 0, |writer| {
+                        try!(writer.writer().write_be_u32(track_id));
+                        try!(writer.writer().write_be_u32(1)); // default_sample_description_index
+                        try!(writer.writer().write_be_u32(0)); // default_sample_duration
+                        try!(writer.writer().write_be_u32(0)); // default_sample_size
+                        writer.writer().write_be_u32(0) // default_sample_flags
+                    }));
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Flushes the samples accumulated so far into one `moof` + `mdat` fragment.
+    pub fn flush_fragment(&mut self) -> Result<(), ()> {
+        if self.tracks.iter().all(|track| track.pending.is_empty()) {
+            return Ok(())
+        }
+
+        self.sequence_number += 1;
+        let sequence_number = self.sequence_number;
+
+        let mut mdat_payload = Vec::new();
+        let mut track_data_offsets = Vec::with_capacity(self.tracks.len());
+        for track in self.tracks.iter() {
+            track_data_offsets.push(mdat_payload.len() as u64);
+            for sample in track.pending.iter() {
+                mdat_payload.extend(sample.data.iter().cloned());
+            }
+        }
+
+        let moof_start = try!(self.output.tell().map_err(|_| ()));
+
+        // Each track's `trun` data offset is relative to the start of this `moof` box, so it
+        // can't be known until `moof`'s own length is known. Write the fragment's tracks to the
+        // real output directly, tracking the absolute file position each `trun`'s data-offset
+        // field landed at, then seek back and patch in the real values once `moof` (and the
+        // `mdat` that follows it) have actually been written.
+        let mut data_offset_patches: Vec<u64> = Vec::with_capacity(self.tracks.len());
+        {
+            let tracks = &self.tracks;
+            let mut writer = BoxWriter::new(&mut self.output);
+            try!(writer.write_box(b"moof", |writer| {
+                try!(writer.write_full_box(b"mfhd", 0, 0, |writer| {
+                    writer.writer().write_be_u32(sequence_number)
+                }));
+
+                for (index, track) in tracks.iter().enumerate() {
+                    let track_id = index as u32 + 1;
+                    let patch_offset = try!(write_traf(writer, track, track_id));
+                    data_offset_patches.push(patch_offset);
+                }
+
+                Ok(())
+            }).map_err(|_| ()));
+        }
+        let moof_end = try!(self.output.tell().map_err(|_| ()));
+
+        try!({
+            let mut writer = BoxWriter::new(&mut self.output);
+            writer.write_box(b"mdat", |writer| writer.writer().write_all(&mdat_payload))
+        }.map_err(|_| ()));
+
+        for (index, patch_offset) in data_offset_patches.iter().enumerate() {
+            let sample_offset = moof_end + 8 + track_data_offsets[index];
+            let data_offset = (sample_offset - moof_start) as u32;
+            try!(self.output.seek(*patch_offset as i64, ::std::old_io::SeekStyle::SeekSet)
+                             .map_err(|_| ()));
+            try!(self.output.write_be_u32(data_offset).map_err(|_| ()));
+        }
+        try!(self.output.seek((moof_end + 8 + mdat_payload.len() as u64) as i64,
+                               ::std::old_io::SeekStyle::SeekSet).map_err(|_| ()));
+
+        for track in self.tracks.iter_mut() {
+            track.total_duration += track.fragment_duration as u64;
+            track.fragment_duration = 0;
+            track.pending.clear();
+        }
+        Ok(())
+    }
+
+    fn should_flush(&self, track_index: usize, sample: &Sample) -> bool {
+        let track = &self.tracks[track_index];
+        if track.pending.is_empty() {
+            return false
+        }
+        let is_video = match track.config { TrackConfig::Video(_) => true, _ => false };
+        if sample.sync && is_video {
+            return true
+        }
+        if let Some(interval) = self.flush_interval_ms {
+            let accumulated_ms = (track.fragment_duration as u64 * 1000) /
+                track.config.timescale() as u64;
+            if accumulated_ms >= interval as u64 {
+                return true
+            }
+        }
+        false
+    }
+}
+
+impl<W: Writer + Seek> ContainerWriter for FragmentedMp4Writer<W> {
+    fn add_video_track(&mut self, config: VideoTrackConfig) -> WriterTrackId {
+        self.tracks.push(FragTrackState {
+            config: TrackConfig::Video(config),
+            pending: Vec::new(),
+            fragment_duration: 0,
+            total_duration: 0,
+        });
+        WriterTrackId(self.tracks.len() as u32 - 1)
+    }
+
+    fn add_audio_track(&mut self, config: AudioTrackConfig) -> WriterTrackId {
+        self.tracks.push(FragTrackState {
+            config: TrackConfig::Audio(config),
+            pending: Vec::new(),
+            fragment_duration: 0,
+            total_duration: 0,
+        });
+        WriterTrackId(self.tracks.len() as u32 - 1)
+    }
+
+    fn write_sample(&mut self, track: WriterTrackId, sample: Sample) -> Result<(), ()> {
+        if !self.wrote_init_segment {
+            try!(self.write_init_segment().map_err(|_| ()));
+            self.wrote_init_segment = true;
+        }
+
+        let WriterTrackId(index) = track;
+        let index = index as usize;
+        if self.should_flush(index, &sample) {
+            try!(self.flush_fragment());
+        }
+
+        let track = &mut self.tracks[index];
+        track.fragment_duration += sample.duration;
+        track.pending.push(PendingSample {
+            data: sample.data.to_vec(),
+            duration: sample.duration,
+            sync: sample.sync,
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ()> {
+        self.flush_fragment()
+    }
+}
+
+/// Writes one track's `traf` box for the current fragment, returning the buffer offset (from the
+/// start of the output stream) of its `trun`'s `data_offset` field, which is patched in by
+/// `FragmentedMp4Writer::flush_fragment` once the enclosing `moof`'s length - and hence the
+/// relative offset to the following `mdat`'s payload - is known.
+fn write_traf<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &FragTrackState, track_id: u32)
+                                 -> IoResult<u64> {
+    let mut data_offset_field = 0u64;
+    try!(writer.write_box(b"traf", |writer| {
+        try!(writer.write_full_box(b"tfhd", 0, 0x020000, |writer| {
+            // flags 0x020000: default-base-is-moof.
+            writer.writer().write_be_u32(track_id)
+        }));
+
+        try!(writer.write_full_box(b"tfdt", 1, 0, |writer| {
+            writer.writer().write_be_u64(track.total_duration)
+        }));
+
+        // flags: data-offset-present | sample-duration-present | sample-size-present |
+        // sample-flags-present
+        writer.write_full_box(b"trun", 0, 0x000701, |writer| {
+            try!(writer.writer().write_be_u32(track.pending.len() as u32));
+            data_offset_field = try!(writer.writer().tell());
+            try!(writer.writer().write_be_u32(0)); // data_offset, patched below
+            for sample in track.pending.iter() {
+                try!(writer.writer().write_be_u32(sample.duration));
+                try!(writer.writer().write_be_u32(sample.data.len() as u32));
+                let flags = if sample.sync { 0x00000000 } else { 0x00010000 }; // sample_is_difference_sample
+                try!(writer.writer().write_be_u32(flags));
+            }
+            Ok(())
+        })
+    }));
+    Ok(data_offset_field)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemWriter;
+
+    use container::{AudioTrackConfig, ContainerWriter, Sample, SampleCodec};
+
+    use super::FragmentedMp4Writer;
+
+    fn read_be_u32(buffer: &[u8], offset: usize) -> u32 {
+        ((buffer[offset] as u32) << 24) | ((buffer[offset + 1] as u32) << 16) |
+            ((buffer[offset + 2] as u32) << 8) | (buffer[offset + 3] as u32)
+    }
+
+    fn read_be_u64(buffer: &[u8], offset: usize) -> u64 {
+        ((read_be_u32(buffer, offset) as u64) << 32) | read_be_u32(buffer, offset + 4) as u64
+    }
+
+    /// Finds every top-level box with the given fourcc, in order, returning each one's payload
+    /// offset (just past the 8-byte header) and payload length. Also usable on a box's own
+    /// payload, since children are laid out the same way.
+    fn find_boxes(buffer: &[u8], fourcc: &[u8; 4]) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = read_be_u32(buffer, offset) as usize;
+            if &buffer[offset + 4..offset + 8] == &fourcc[..] {
+                matches.push((offset + 8, size - 8));
+            }
+            offset += size;
+        }
+        matches
+    }
+
+    /// A regression test for fragments after the first claiming to start at presentation time
+    /// zero: each fragment's `tfdt` `base_time` must equal the sum of every earlier fragment's
+    /// sample durations on that track, not zero, or timeline reconstruction/seeking breaks for
+    /// any consumer of more than one fragment.
+    #[test]
+    fn tfdt_base_time_accumulates_across_fragments() {
+        let mut writer = FragmentedMp4Writer::new(MemWriter::new(), None);
+        let track = writer.add_audio_track(AudioTrackConfig {
+            sample_rate: 48000,
+            channels: 2,
+            codec: SampleCodec::Aac { decoder_configuration: vec![1, 2] },
+        });
+
+        let first_fragment: Vec<Vec<u8>> = vec![vec![0xaau8; 4], vec![0xaau8; 4]];
+        for data in first_fragment.iter() {
+            writer.write_sample(track, Sample { data: data, duration: 1024, sync: true }).unwrap();
+        }
+        writer.flush_fragment().unwrap();
+
+        writer.write_sample(track, Sample { data: &vec![0xbbu8; 4], duration: 1024, sync: true })
+              .unwrap();
+        writer.finish().unwrap();
+
+        let buffer = writer.output.get_ref().to_vec();
+        let moofs = find_boxes(&buffer, b"moof");
+        assert_eq!(moofs.len(), 2);
+
+        let base_times: Vec<u64> = moofs.iter().map(|&(moof_offset, moof_len)| {
+            let moof = &buffer[moof_offset..moof_offset + moof_len];
+            let (traf_offset, traf_len) = find_boxes(moof, b"traf")[0];
+            let traf = &moof[traf_offset..traf_offset + traf_len];
+            let (tfdt_offset, _) = find_boxes(traf, b"tfdt")[0];
+            // `tfdt`'s full-box payload is version/flags (4 bytes) then, for version 1, a
+            // 64-bit base_time.
+            read_be_u64(traf, tfdt_offset + 4)
+        }).collect();
+
+        assert_eq!(base_times[0], 0);
+        assert_eq!(base_times[1], 2048);
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! ISO Base Media File Format (MP4) support.
+//!
+//! This module currently provides only the write side (`mp4::writer::Mp4Writer` for finalized
+//! files, `mp4::fragmented::FragmentedMp4Writer` for CMAF-style streaming output); there is no
+//! MP4 demuxer registered with `container::REGISTERED_CONTAINER_READERS` yet.
+
+pub mod boxes;
+pub mod fragmented;
+pub mod writer;
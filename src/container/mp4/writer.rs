@@ -0,0 +1,565 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A muxer that serializes decoded/re-encoded tracks into a single, finalized ISOBMFF (MP4)
+//! file: `ftyp`, `moov` (with one `trak`/`mdia`/`minf`/`stbl` per track), and a trailing `mdat`
+//! holding every sample's payload.
+//!
+//! Samples are buffered in memory as they arrive via `write_sample`, because the sample tables
+//! (`stsz`, `stco`, ...) can't be written until every sample's size and position are known; the
+//! actual file is only produced once `finish` is called. See `container::mp4::fragmented` for a
+//! mode that avoids this buffering by flushing self-contained fragments incrementally.
+
+use std::old_io::{IoError, IoErrorKind, IoResult, MemWriter, Seek, Writer};
+
+use container::{AudioTrackConfig, ContainerWriter, Sample, SampleCodec, VideoTrackConfig,
+                WriterTrackId};
+use container::mp4::boxes::BoxWriter;
+
+/// One buffered sample, recorded as it is written so that the sample tables can be built once
+/// every track has seen its last sample.
+struct SampleRecord {
+    /// Offset of this sample's payload within `Mp4Writer::mdat`.
+    offset: u64,
+    size: u32,
+    duration: u32,
+    sync: bool,
+}
+
+enum TrackConfig {
+    Video(VideoTrackConfig),
+    Audio(AudioTrackConfig),
+}
+
+struct TrackState {
+    config: TrackConfig,
+    samples: Vec<SampleRecord>,
+}
+
+impl TrackState {
+    fn timescale(&self) -> u32 {
+        match self.config {
+            TrackConfig::Video(ref config) => config.timescale,
+            TrackConfig::Audio(ref config) => config.sample_rate,
+        }
+    }
+
+    fn codec(&self) -> &SampleCodec {
+        match self.config {
+            TrackConfig::Video(ref config) => &config.codec,
+            TrackConfig::Audio(ref config) => &config.codec,
+        }
+    }
+
+    fn duration(&self) -> u64 {
+        self.samples.iter().map(|sample| sample.duration as u64).sum()
+    }
+}
+
+/// Writes tracks out as a single finalized MP4 file.
+pub struct Mp4Writer<W> {
+    output: W,
+    tracks: Vec<TrackState>,
+    mdat: Vec<u8>,
+}
+
+impl<W: Writer + Seek> Mp4Writer<W> {
+    /// Creates a new, empty writer around the given sink. Call `add_video_track`/
+    /// `add_audio_track` to register tracks, `write_sample` to append data, and `finish` to
+    /// flush the finished file.
+    pub fn new(output: W) -> Mp4Writer<W> {
+        Mp4Writer {
+            output: output,
+            tracks: Vec::new(),
+            mdat: Vec::new(),
+        }
+    }
+
+    /// Picks the `ftyp` major brand and compatible-brands list based on the codecs that have
+    /// been registered so far.
+    fn brands(&self) -> (&'static [u8; 4], Vec<&'static [u8; 4]>) {
+        let mut compatible: Vec<&'static [u8; 4]> = vec![b"isom", b"mp42"];
+        for track in self.tracks.iter() {
+            match *track.codec() {
+                SampleCodec::H264 { .. } => compatible.push(b"avc1"),
+                SampleCodec::H265 { .. } => compatible.push(b"hev1"),
+                SampleCodec::Vp9 { .. } => compatible.push(b"vp09"),
+                SampleCodec::Aac { .. } | SampleCodec::Opus { .. } => {}
+            }
+        }
+        (b"isom", compatible)
+    }
+
+    fn write_ftyp(&mut self) -> IoResult<()> {
+        let (major_brand, compatible_brands) = self.brands();
+        let mut writer = BoxWriter::new(&mut self.output);
+        writer.write_box(b"ftyp", |writer| {
+            try!(writer.writer().write_all(major_brand));
+            try!(writer.writer().write_be_u32(0)); // minor_version
+            for brand in compatible_brands.iter() {
+                try!(writer.writer().write_all(*brand));
+            }
+            Ok(())
+        })
+    }
+
+    /// Serializes `moov` into an in-memory buffer, recording the byte offset of each track's
+    /// `stco`/`co64` entries so that they can be backpatched once the real, absolute `mdat`
+    /// sample offsets are known (which in turn depend on `moov`'s own length).
+    fn build_moov(&self) -> IoResult<(Vec<u8>, Vec<Vec<u64>>)> {
+        let mut buffer = MemWriter::new();
+        let mut stco_patch_offsets = Vec::with_capacity(self.tracks.len());
+        {
+            let mut writer = BoxWriter::new(&mut buffer);
+            try!(writer.write_box(b"moov", |writer| {
+                let movie_timescale = 1000u32;
+                let movie_duration = self.tracks.iter().map(|track| {
+                    (track.duration() * movie_timescale as u64) / track.timescale() as u64
+                }).max().unwrap_or(0);
+
+                try!(writer.write_full_box(b"mvhd", 0, 0, |writer| {
+                    try!(writer.writer().write_be_u32(0)); // creation_time
+                    try!(writer.writer().write_be_u32(0)); // modification_time
+                    try!(writer.writer().write_be_u32(movie_timescale));
+                    try!(writer.writer().write_be_u32(movie_duration as u32));
+                    try!(writer.writer().write_be_u32(0x00010000)); // rate, 1.0
+                    try!(writer.writer().write_be_u16(0x0100)); // volume, 1.0
+                    try!(writer.writer().write_be_u16(0)); // reserved
+                    try!(writer.writer().write_be_u64(0)); // reserved[2]
+                    for value in IDENTITY_MATRIX.iter() {
+                        try!(writer.writer().write_be_u32(*value));
+                    }
+                    for _ in 0..6 {
+                        try!(writer.writer().write_be_u32(0)); // pre_defined
+                    }
+                    try!(writer.writer().write_be_u32(self.tracks.len() as u32 + 1)); // next_track_id
+                    Ok(())
+                }));
+
+                for (index, track) in self.tracks.iter().enumerate() {
+                    let offset = try!(write_trak(writer, track, index as u32 + 1, movie_timescale));
+                    stco_patch_offsets.push(offset);
+                }
+
+                Ok(())
+            }));
+        }
+        Ok((buffer.into_inner(), stco_patch_offsets))
+    }
+
+    fn write_mdat(&mut self) -> IoResult<()> {
+        let mut writer = BoxWriter::new(&mut self.output);
+        writer.write_box(b"mdat", |writer| {
+            writer.writer().write_all(&self.mdat)
+        })
+    }
+}
+
+impl<W: Writer + Seek> ContainerWriter for Mp4Writer<W> {
+    fn add_video_track(&mut self, config: VideoTrackConfig) -> WriterTrackId {
+        self.tracks.push(TrackState { config: TrackConfig::Video(config), samples: Vec::new() });
+        WriterTrackId(self.tracks.len() as u32 - 1)
+    }
+
+    fn add_audio_track(&mut self, config: AudioTrackConfig) -> WriterTrackId {
+        self.tracks.push(TrackState { config: TrackConfig::Audio(config), samples: Vec::new() });
+        WriterTrackId(self.tracks.len() as u32 - 1)
+    }
+
+    fn write_sample(&mut self, track: WriterTrackId, sample: Sample) -> Result<(), ()> {
+        let WriterTrackId(index) = track;
+        let offset = self.mdat.len() as u64;
+        self.mdat.extend(sample.data.iter().cloned());
+        self.tracks[index as usize].samples.push(SampleRecord {
+            offset: offset,
+            size: sample.data.len() as u32,
+            duration: sample.duration,
+            sync: sample.sync,
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ()> {
+        self.write_ftyp().map_err(|_| ()).and_then(|_| {
+            let (mut moov, stco_patch_offsets) = try!(self.build_moov().map_err(|_| ()));
+
+            // `moov`'s length is now fixed, so every sample's absolute file offset is known:
+            // it's the length of everything that precedes `mdat`'s payload, plus the sample's
+            // offset within `mdat` itself. `ftyp`'s box length is its 8-byte header plus a
+            // 4-byte major_brand, a 4-byte minor_version, and 4 bytes per compatible brand.
+            let ftyp_len = 8 + 4 * (2 + self.brands().1.len() as u64);
+            let mdat_header_len = 8u64;
+            let base_offset = ftyp_len + moov.len() as u64 + mdat_header_len;
+
+            for (track, patch_offset) in self.tracks.iter().zip(stco_patch_offsets.into_iter()) {
+                for (sample_index, sample) in track.samples.iter().enumerate() {
+                    let absolute_offset = base_offset + sample.offset;
+                    // `stco` entries are 32-bit; `write_stco` doesn't yet emit `co64` for files
+                    // whose sample data would need one (see its doc comment), so refuse to
+                    // silently wrap a too-large offset into a corrupt, truncated one.
+                    if absolute_offset > ::std::u32::MAX as u64 {
+                        return Err(())
+                    }
+                    patch_be_u32(&mut moov,
+                                 patch_offset + sample_index as u64 * 4,
+                                 absolute_offset as u32);
+                }
+            }
+
+            try!(self.output.write_all(&moov).map_err(|_| ()));
+            self.write_mdat().map_err(|_| ())
+        })
+    }
+}
+
+/// The identity entry for a `mvhd`/`tkhd` unity transformation matrix, in 16.16 fixed point
+/// except for the last column, which is 2.30 fixed point.
+const IDENTITY_MATRIX: [u32; 9] =
+    [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+
+fn patch_be_u32(buffer: &mut [u8], offset: u64, value: u32) {
+    let offset = offset as usize;
+    buffer[offset] = (value >> 24) as u8;
+    buffer[offset + 1] = (value >> 16) as u8;
+    buffer[offset + 2] = (value >> 8) as u8;
+    buffer[offset + 3] = value as u8;
+}
+
+/// Writes one track's `trak` box, returning the buffer offset of the first entry of its
+/// `stco` box (where `Mp4Writer::finish` will later patch in real, absolute sample offsets).
+fn write_trak<W: Writer + Seek>(writer: &mut BoxWriter<W>,
+                                 track: &TrackState,
+                                 track_id: u32,
+                                 movie_timescale: u32)
+                                 -> IoResult<u64> {
+    let mut stco_offset = 0u64;
+    try!(writer.write_box(b"trak", |writer| {
+        let track_duration = (track.duration() * movie_timescale as u64) / track.timescale() as u64;
+
+        try!(writer.write_full_box(b"tkhd", 0, 0x000007, |writer| {
+            try!(writer.writer().write_be_u32(0)); // creation_time
+            try!(writer.writer().write_be_u32(0)); // modification_time
+            try!(writer.writer().write_be_u32(track_id));
+            try!(writer.writer().write_be_u32(0)); // reserved
+            try!(writer.writer().write_be_u32(track_duration as u32));
+            try!(writer.writer().write_be_u64(0)); // reserved[2]
+            try!(writer.writer().write_be_u16(0)); // layer
+            try!(writer.writer().write_be_u16(0)); // alternate_group
+            let is_audio = match track.config { TrackConfig::Audio(_) => true, _ => false };
+            try!(writer.writer().write_be_u16(if is_audio { 0x0100 } else { 0 })); // volume
+            try!(writer.writer().write_be_u16(0)); // reserved
+            for value in IDENTITY_MATRIX.iter() {
+                try!(writer.writer().write_be_u32(*value));
+            }
+            let (width, height) = match track.config {
+                TrackConfig::Video(ref config) => (config.width, config.height),
+                TrackConfig::Audio(_) => (0, 0),
+            };
+            try!(writer.writer().write_be_u32((width as u32) << 16));
+            try!(writer.writer().write_be_u32((height as u32) << 16));
+            Ok(())
+        }));
+
+        try!(writer.write_box(b"mdia", |writer| {
+            try!(writer.write_full_box(b"mdhd", 0, 0, |writer| {
+                try!(writer.writer().write_be_u32(0)); // creation_time
+                try!(writer.writer().write_be_u32(0)); // modification_time
+                try!(writer.writer().write_be_u32(track.timescale()));
+                try!(writer.writer().write_be_u32(track.duration() as u32));
+                try!(writer.writer().write_be_u16(0x55c4)); // language: "und"
+                try!(writer.writer().write_be_u16(0)); // pre_defined
+                Ok(())
+            }));
+
+            let (handler_type, handler_name): (&[u8; 4], &[u8]) = match track.config {
+                TrackConfig::Video(_) => (b"vide", b"VideoHandler\0"),
+                TrackConfig::Audio(_) => (b"soun", b"SoundHandler\0"),
+            };
+            try!(writer.write_full_box(b"hdlr", 0, 0, |writer| {
+                try!(writer.writer().write_be_u32(0)); // pre_defined
+                try!(writer.writer().write_all(handler_type));
+                try!(writer.writer().write_be_u64(0)); // reserved[3] (first 8 of 12 bytes)
+                try!(writer.writer().write_be_u32(0));
+                writer.writer().write_all(handler_name)
+            }));
+
+            try!(writer.write_box(b"minf", |writer| {
+                match track.config {
+                    TrackConfig::Video(_) => {
+                        try!(writer.write_full_box(b"vmhd", 0, 1, |writer| {
+                            try!(writer.writer().write_be_u16(0)); // graphicsmode
+                            writer.writer().write_all(&[0u8; 6]) // opcolor[3]
+                        }));
+                    }
+                    TrackConfig::Audio(_) => {
+                        try!(writer.write_full_box(b"smhd", 0, 0, |writer| {
+                            try!(writer.writer().write_be_u16(0)); // balance
+                            writer.writer().write_be_u16(0) // reserved
+                        }));
+                    }
+                }
+
+                try!(writer.write_box(b"dinf", |writer| {
+                    writer.write_full_box(b"dref", 0, 0, |writer| {
+                        try!(writer.writer().write_be_u32(1)); // entry_count
+                        writer.write_full_box(b"url ", 0, 1, |_| Ok(()))
+                    })
+                }));
+
+                try!(writer.write_box(b"stbl", |writer| {
+                    try!(write_stsd(writer, track));
+                    try!(write_stts(writer, track));
+                    try!(write_stsc(writer, track));
+                    try!(write_stsz(writer, track));
+                    try!(write_stss(writer, track));
+                    stco_offset = try!(write_stco(writer, track));
+                    Ok(())
+                }));
+
+                Ok(())
+            }));
+
+            Ok(())
+        }));
+
+        Ok(())
+    }));
+    Ok(stco_offset)
+}
+
+fn write_stsd<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<()> {
+    writer.write_full_box(b"stsd", 0, 0, |writer| {
+        try!(writer.writer().write_be_u32(1)); // entry_count
+        match track.config {
+            TrackConfig::Video(ref config) => write_visual_sample_entry(writer, config),
+            TrackConfig::Audio(ref config) => write_audio_sample_entry(writer, config),
+        }
+    })
+}
+
+pub fn write_visual_sample_entry<W: Writer + Seek>(writer: &mut BoxWriter<W>,
+                                                config: &VideoTrackConfig)
+                                                -> IoResult<()> {
+    let (fourcc, config_fourcc, config_bytes): (&[u8; 4], &[u8; 4], &[u8]) = match config.codec {
+        SampleCodec::H264 { ref decoder_configuration } => (b"avc1", b"avcC", decoder_configuration),
+        SampleCodec::H265 { ref decoder_configuration } => (b"hev1", b"hvcC", decoder_configuration),
+        SampleCodec::Vp9 { ref decoder_configuration } => (b"vp09", b"vpcC", decoder_configuration),
+        // Nothing in the type system stops a `VideoTrackConfig` from being built with an audio
+        // codec, so this has to be a recoverable error, not a panic, like every other way this
+        // writer can fail.
+        _ => return Err(IoError {
+            kind: IoErrorKind::InvalidInput,
+            desc: "video track's codec is not a video codec",
+            detail: None,
+        }),
+    };
+    writer.write_box(fourcc, |writer| {
+        try!(writer.writer().write_all(&[0u8; 6])); // reserved[6]
+        try!(writer.writer().write_be_u16(1)); // data_reference_index
+        try!(writer.writer().write_be_u16(0)); // pre_defined
+        try!(writer.writer().write_be_u16(0)); // reserved
+        try!(writer.writer().write_be_u64(0)); // pre_defined[3] (first 8 of 12)
+        try!(writer.writer().write_be_u32(0));
+        try!(writer.writer().write_be_u16(config.width));
+        try!(writer.writer().write_be_u16(config.height));
+        try!(writer.writer().write_be_u32(0x00480000)); // horizresolution, 72 dpi
+        try!(writer.writer().write_be_u32(0x00480000)); // vertresolution, 72 dpi
+        try!(writer.writer().write_be_u32(0)); // reserved
+        try!(writer.writer().write_be_u16(1)); // frame_count
+        try!(writer.writer().write_all(&[0u8; 32])); // compressorname
+        try!(writer.writer().write_be_u16(0x0018)); // depth
+        try!(writer.writer().write_be_i16(-1)); // pre_defined
+        writer.write_box(config_fourcc, |writer| writer.writer().write_all(config_bytes))
+    })
+}
+
+pub fn write_audio_sample_entry<W: Writer + Seek>(writer: &mut BoxWriter<W>,
+                                               config: &AudioTrackConfig)
+                                               -> IoResult<()> {
+    let (fourcc, config_fourcc, config_bytes): (&[u8; 4], &[u8; 4], &[u8]) = match config.codec {
+        SampleCodec::Aac { ref decoder_configuration } => (b"mp4a", b"esds", decoder_configuration),
+        SampleCodec::Opus { ref decoder_configuration } => (b"Opus", b"dOps", decoder_configuration),
+        // Nothing in the type system stops an `AudioTrackConfig` from being built with a video
+        // codec, so this has to be a recoverable error, not a panic, like every other way this
+        // writer can fail.
+        _ => return Err(IoError {
+            kind: IoErrorKind::InvalidInput,
+            desc: "audio track's codec is not an audio codec",
+            detail: None,
+        }),
+    };
+    writer.write_box(fourcc, |writer| {
+        try!(writer.writer().write_all(&[0u8; 6])); // reserved[6]
+        try!(writer.writer().write_be_u16(1)); // data_reference_index
+        try!(writer.writer().write_be_u64(0)); // reserved[2]
+        try!(writer.writer().write_be_u16(config.channels));
+        try!(writer.writer().write_be_u16(16)); // samplesize
+        try!(writer.writer().write_be_u16(0)); // pre_defined
+        try!(writer.writer().write_be_u16(0)); // reserved
+        try!(writer.writer().write_be_u32(config.sample_rate << 16));
+        writer.write_box(config_fourcc, |writer| writer.writer().write_all(config_bytes))
+    })
+}
+
+fn write_stts<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<()> {
+    writer.write_full_box(b"stts", 0, 0, |writer| {
+        // Coalesce consecutive samples with equal duration into a single (count, delta) entry.
+        let mut entries: Vec<(u32, u32)> = Vec::new();
+        for sample in track.samples.iter() {
+            match entries.last_mut() {
+                Some(&mut (ref mut count, delta)) if delta == sample.duration => *count += 1,
+                _ => entries.push((1, sample.duration)),
+            }
+        }
+        try!(writer.writer().write_be_u32(entries.len() as u32));
+        for (count, delta) in entries {
+            try!(writer.writer().write_be_u32(count));
+            try!(writer.writer().write_be_u32(delta));
+        }
+        Ok(())
+    })
+}
+
+fn write_stsc<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<()> {
+    writer.write_full_box(b"stsc", 0, 0, |writer| {
+        try!(writer.writer().write_be_u32(if track.samples.is_empty() { 0 } else { 1 }));
+        if !track.samples.is_empty() {
+            try!(writer.writer().write_be_u32(1)); // first_chunk
+            try!(writer.writer().write_be_u32(1)); // samples_per_chunk: one chunk per `stco` entry
+            try!(writer.writer().write_be_u32(1)); // sample_description_index
+        }
+        Ok(())
+    })
+}
+
+fn write_stsz<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<()> {
+    writer.write_full_box(b"stsz", 0, 0, |writer| {
+        try!(writer.writer().write_be_u32(0)); // sample_size (0: sizes given per-sample below)
+        try!(writer.writer().write_be_u32(track.samples.len() as u32));
+        for sample in track.samples.iter() {
+            try!(writer.writer().write_be_u32(sample.size));
+        }
+        Ok(())
+    })
+}
+
+fn write_stss<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<()> {
+    let sync_sample_numbers: Vec<u32> = track.samples.iter()
+                                                      .enumerate()
+                                                      .filter(|&(_, sample)| sample.sync)
+                                                      .map(|(index, _)| index as u32 + 1)
+                                                      .collect();
+    // Every sample is a sync sample (e.g. all-intra video, or audio): omit `stss` entirely, per
+    // the spec, rather than writing a table that lists every sample.
+    if sync_sample_numbers.len() == track.samples.len() {
+        return Ok(())
+    }
+    writer.write_full_box(b"stss", 0, 0, |writer| {
+        try!(writer.writer().write_be_u32(sync_sample_numbers.len() as u32));
+        for number in sync_sample_numbers {
+            try!(writer.writer().write_be_u32(number));
+        }
+        Ok(())
+    })
+}
+
+/// Writes the `stco` box, with every entry initially zero, and returns the buffer offset of the
+/// first entry so that `Mp4Writer::finish` can patch in the real, absolute offsets once they're
+/// known.
+///
+/// `co64`, needed once sample data no longer fits a 32-bit offset, isn't implemented yet;
+/// `Mp4Writer::finish` fails instead of silently truncating an offset that doesn't fit.
+fn write_stco<W: Writer + Seek>(writer: &mut BoxWriter<W>, track: &TrackState) -> IoResult<u64> {
+    let mut first_entry_offset = 0u64;
+    writer.write_full_box(b"stco", 0, 0, |writer| {
+        try!(writer.writer().write_be_u32(track.samples.len() as u32));
+        first_entry_offset = try!(writer.writer().tell());
+        for _ in track.samples.iter() {
+            try!(writer.writer().write_be_u32(0));
+        }
+        Ok(())
+    }).map(|_| first_entry_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemWriter;
+
+    use container::{ContainerWriter, Sample, SampleCodec, VideoTrackConfig};
+
+    use super::Mp4Writer;
+
+    fn read_be_u32(buffer: &[u8], offset: usize) -> u32 {
+        ((buffer[offset] as u32) << 24) | ((buffer[offset + 1] as u32) << 16) |
+            ((buffer[offset + 2] as u32) << 8) | (buffer[offset + 3] as u32)
+    }
+
+    /// Finds the first top-level box with the given fourcc, returning the offset of its payload
+    /// (just past the 8-byte header) and the payload's length.
+    fn find_box(buffer: &[u8], fourcc: &[u8; 4]) -> (usize, usize) {
+        let mut offset = 0;
+        loop {
+            let size = read_be_u32(buffer, offset) as usize;
+            if &buffer[offset + 4..offset + 8] == &fourcc[..] {
+                return (offset + 8, size - 8)
+            }
+            offset += size;
+        }
+    }
+
+    /// A regression test for the `ftyp_len` off-by-four bug: every `stco` entry must point at
+    /// the exact byte, within the finished file, where that sample's data actually landed in
+    /// `mdat`, not four bytes short (or anywhere else).
+    #[test]
+    fn stco_entries_point_at_the_right_mdat_offsets() {
+        let mut writer = Mp4Writer::new(MemWriter::new());
+        let track = writer.add_video_track(VideoTrackConfig {
+            width: 64,
+            height: 64,
+            timescale: 600,
+            codec: SampleCodec::H264 { decoder_configuration: vec![1, 2, 3] },
+        });
+
+        let samples: Vec<Vec<u8>> = vec![vec![0xaau8; 10], vec![0xbbu8; 20], vec![0xccu8; 5]];
+        for (index, data) in samples.iter().enumerate() {
+            writer.write_sample(track, Sample {
+                data: data,
+                duration: 30,
+                sync: index == 0,
+            }).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let buffer = writer.output.get_ref().to_vec();
+
+        let (mdat_payload_offset, _) = find_box(&buffer, b"mdat");
+        let (moov_offset, moov_len) = find_box(&buffer, b"moov");
+        let moov = &buffer[moov_offset..moov_offset + moov_len];
+        // `stco` is nested inside `trak`/`mdia`/`minf`/`stbl`, so descend one level at a time.
+        let (stco_payload_offset, _) = [b"trak", b"mdia", b"minf", b"stbl", b"stco"].iter()
+            .fold((0, moov.len()), |(offset, len), fourcc| {
+                let (child_offset, child_len) = find_box(&moov[offset..offset + len], *fourcc);
+                (offset + child_offset, child_len)
+            });
+
+        // `stco`'s full-box payload is version/flags (4 bytes), entry_count (4 bytes), then one
+        // 4-byte entry per sample.
+        let entry_count = read_be_u32(moov, stco_payload_offset + 4);
+        assert_eq!(entry_count as usize, samples.len());
+
+        let mut expected_mdat_offset = 0usize;
+        for (index, data) in samples.iter().enumerate() {
+            let entry = read_be_u32(moov, stco_payload_offset + 8 + index * 4) as usize;
+            assert_eq!(entry, mdat_payload_offset + expected_mdat_offset,
+                       "stco entry {} points at the wrong byte", index);
+            assert_eq!(&buffer[entry..entry + data.len()], data.as_slice());
+            expected_mdat_offset += data.len();
+        }
+    }
+}
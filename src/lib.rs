@@ -0,0 +1,27 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `rust-media` is a pure Rust media framework designed for use with Servo. It provides
+//! demultiplexing, decoding, and (where available) encoding and multiplexing of common
+//! audio and video formats behind small, composable traits.
+
+#![feature(collections, core, io, libc, std_misc)]
+
+extern crate libc;
+
+#[macro_use]
+extern crate log;
+
+pub mod audioencoder;
+pub mod audioformat;
+pub mod container;
+pub mod pixelformat;
+pub mod playback;
+pub mod videodecoder;
+pub mod videoencoder;
@@ -0,0 +1,271 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pixel formats that decoders may produce and conversions between them.
+
+/// The pixel format that a decoded video frame is stored in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PixelFormat<'a> {
+    /// Planar 4:2:0 YUV, as produced by most software video decoders.
+    I420,
+    /// Semi-planar 4:2:0 YUV (one luma plane, one interleaved chroma plane).
+    NV12,
+    /// 8 bits per pixel, indexed into the given RGB palette.
+    Indexed(&'a [(u8, u8, u8)]),
+    /// 24-bit packed RGB.
+    Rgb24,
+}
+
+impl<'a> PixelFormat<'a> {
+    /// Returns the number of discrete planes that this pixel format is made up of.
+    pub fn planes(&self) -> usize {
+        match *self {
+            PixelFormat::I420 => 3,
+            PixelFormat::NV12 => 2,
+            PixelFormat::Indexed(_) | PixelFormat::Rgb24 => 1,
+        }
+    }
+}
+
+/// A marker pixel format corresponding to 24-bit packed RGB, for callers that want to name the
+/// conversion target without constructing a `PixelFormat` value by hand.
+pub struct Rgb24;
+
+/// Conversion between pixel formats, with an optional resize of the image in the same pass.
+///
+/// `rust-media` doesn't attempt to implement every conversion between every pair of formats;
+/// decoders only ever need to target the small set of formats that `rust-media` understands
+/// natively (see `PixelFormat`), so the matrix of supported conversions is correspondingly small.
+/// The implementation for `PixelFormat` handles only same-format pairs (`I420`, `NV12` and
+/// `Rgb24` each to itself), copying or rescaling; every other pair, including any conversion
+/// from or to `Indexed`, returns `Err(())`.
+pub trait ConvertPixelFormat {
+    /// Converts `width` by `height` pixels from `src_pixels`/`src_strides` (in `self`'s format)
+    /// into `dest_pixels`/`dest_strides` (in `dest_format`), at the source's own dimensions.
+    ///
+    /// `src_pixels` and `dest_pixels` hold one slice per plane, in the order implied by
+    /// `PixelFormat::planes()`; `src_strides` and `dest_strides` give the corresponding
+    /// byte-per-row stride of each plane.
+    fn convert(&self,
+               dest_format: &PixelFormat,
+               dest_pixels: &mut [&mut [u8]],
+               dest_strides: &[usize],
+               src_pixels: &[&[u8]],
+               src_strides: &[usize],
+               width: usize,
+               height: usize)
+               -> Result<(), ()> {
+        self.convert_scaled(dest_format,
+                            dest_pixels,
+                            dest_strides,
+                            (width, height),
+                            src_pixels,
+                            src_strides,
+                            (width, height))
+    }
+
+    /// Like `convert`, but additionally resizes the image from `src_dims` to `dest_dims` as part
+    /// of the conversion.
+    fn convert_scaled(&self,
+                       dest_format: &PixelFormat,
+                       dest_pixels: &mut [&mut [u8]],
+                       dest_strides: &[usize],
+                       dest_dims: (usize, usize),
+                       src_pixels: &[&[u8]],
+                       src_strides: &[usize],
+                       src_dims: (usize, usize))
+                       -> Result<(), ()>;
+}
+
+impl<'a> ConvertPixelFormat for PixelFormat<'a> {
+    fn convert_scaled(&self,
+                       dest_format: &PixelFormat,
+                       dest_pixels: &mut [&mut [u8]],
+                       dest_strides: &[usize],
+                       dest_dims: (usize, usize),
+                       src_pixels: &[&[u8]],
+                       src_strides: &[usize],
+                       src_dims: (usize, usize))
+                       -> Result<(), ()> {
+        match (self, dest_format) {
+            (&PixelFormat::I420, &PixelFormat::I420) if dest_dims == src_dims => {
+                let chroma_dims = (chroma_dim(src_dims.0), chroma_dim(src_dims.1));
+                copy_plane(dest_pixels[0], dest_strides[0], src_pixels[0], src_strides[0],
+                           src_dims.0, src_dims.1);
+                for plane in 1..3 {
+                    copy_plane(dest_pixels[plane], dest_strides[plane],
+                               src_pixels[plane], src_strides[plane],
+                               chroma_dims.0, chroma_dims.1);
+                }
+                Ok(())
+            }
+            (&PixelFormat::Rgb24, &PixelFormat::Rgb24) if dest_dims == src_dims => {
+                copy_plane(dest_pixels[0],
+                           dest_strides[0],
+                           src_pixels[0],
+                           src_strides[0],
+                           src_dims.0 * 3,
+                           src_dims.1);
+                Ok(())
+            }
+            (&PixelFormat::I420, &PixelFormat::I420) => {
+                scale_plane(dest_pixels[0], dest_strides[0], dest_dims,
+                            src_pixels[0], src_strides[0], src_dims,
+                            1);
+                let src_chroma_dims = (chroma_dim(src_dims.0), chroma_dim(src_dims.1));
+                let dest_chroma_dims = (chroma_dim(dest_dims.0), chroma_dim(dest_dims.1));
+                for plane in 1..3 {
+                    scale_plane(dest_pixels[plane], dest_strides[plane], dest_chroma_dims,
+                                src_pixels[plane], src_strides[plane], src_chroma_dims,
+                                1);
+                }
+                Ok(())
+            }
+            (&PixelFormat::NV12, &PixelFormat::NV12) => {
+                scale_plane(dest_pixels[0], dest_strides[0], dest_dims,
+                            src_pixels[0], src_strides[0], src_dims,
+                            1);
+                let src_chroma_dims = (chroma_dim(src_dims.0), chroma_dim(src_dims.1));
+                let dest_chroma_dims = (chroma_dim(dest_dims.0), chroma_dim(dest_dims.1));
+                scale_plane(dest_pixels[1], dest_strides[1], dest_chroma_dims,
+                            src_pixels[1], src_strides[1], src_chroma_dims,
+                            2);
+                Ok(())
+            }
+            (&PixelFormat::Rgb24, &PixelFormat::Rgb24) => {
+                scale_plane(dest_pixels[0], dest_strides[0], dest_dims,
+                            src_pixels[0], src_strides[0], src_dims,
+                            3);
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Copies a single plane row by row, allowing the source and destination strides to differ.
+/// `row_bytes` is the number of bytes to copy per row (already accounting for any bytes-per-pixel
+/// multiplier), not necessarily the same as `width`.
+fn copy_plane(dest: &mut [u8],
+              dest_stride: usize,
+              src: &[u8],
+              src_stride: usize,
+              row_bytes: usize,
+              height: usize) {
+    for row in 0..height {
+        let dest_row = &mut dest[(row * dest_stride)..(row * dest_stride + row_bytes)];
+        let src_row = &src[(row * src_stride)..(row * src_stride + row_bytes)];
+        dest_row.clone_from_slice(src_row);
+    }
+}
+
+/// The dimension of a 4:2:0-subsampled chroma plane corresponding to a luma dimension of `dim`,
+/// rounding up for odd sizes (as `I420`/`NV12` decoders are expected to do for their own planes).
+fn chroma_dim(dim: usize) -> usize {
+    (dim + 1) / 2
+}
+
+/// Separably scales one plane with `components`-per-pixel samples (1 for a planar luma or chroma
+/// plane, 2 for `NV12`'s interleaved chroma plane, 3 for packed RGB) from `src_dims` to
+/// `dest_dims` using bilinear interpolation, blending the four nearest source texels into each
+/// destination texel.
+///
+/// Source coordinates are computed with the standard pixel-center mapping
+/// `src = (dest + 0.5) * src_size / dest_size - 0.5`, so that scaling to the same size is the
+/// identity and upscaling/downscaling both sample symmetrically around texel centers; coordinates
+/// that fall outside the source are clamped to its border.
+fn scale_plane(dest: &mut [u8],
+               dest_stride: usize,
+               dest_dims: (usize, usize),
+               src: &[u8],
+               src_stride: usize,
+               src_dims: (usize, usize),
+               components: usize) {
+    let (dest_width, dest_height) = dest_dims;
+    let (src_width, src_height) = src_dims;
+    if src_width == 0 || src_height == 0 || dest_width == 0 || dest_height == 0 {
+        return
+    }
+
+    let x_ratio = src_width as f32 / dest_width as f32;
+    let y_ratio = src_height as f32 / dest_height as f32;
+
+    for dest_y in 0..dest_height {
+        let sy = (dest_y as f32 + 0.5) * y_ratio - 0.5;
+        let y0 = clamp_coord(sy.floor() as isize, src_height);
+        let y1 = clamp_coord(sy.floor() as isize + 1, src_height);
+        let fy = sy - sy.floor();
+
+        for dest_x in 0..dest_width {
+            let sx = (dest_x as f32 + 0.5) * x_ratio - 0.5;
+            let x0 = clamp_coord(sx.floor() as isize, src_width);
+            let x1 = clamp_coord(sx.floor() as isize + 1, src_width);
+            let fx = sx - sx.floor();
+
+            for component in 0..components {
+                let top_left = src[y0 * src_stride + x0 * components + component] as f32;
+                let top_right = src[y0 * src_stride + x1 * components + component] as f32;
+                let bottom_left = src[y1 * src_stride + x0 * components + component] as f32;
+                let bottom_right = src[y1 * src_stride + x1 * components + component] as f32;
+
+                let top = top_left + (top_right - top_left) * fx;
+                let bottom = bottom_left + (bottom_right - bottom_left) * fx;
+                let value = top + (bottom - top) * fy;
+
+                dest[dest_y * dest_stride + dest_x * components + component] =
+                    (value + 0.5) as u8;
+            }
+        }
+    }
+}
+
+/// Clamps a (possibly negative, possibly past-the-end) source coordinate to the valid
+/// `[0, size - 1]` range, for sampling at the border of a plane.
+fn clamp_coord(coord: isize, size: usize) -> usize {
+    if coord < 0 {
+        0
+    } else if coord as usize >= size {
+        size - 1
+    } else {
+        coord as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scale_plane;
+
+    /// Scaling a plane to its own dimensions should reproduce it exactly: the pixel-center
+    /// mapping is designed so that this case is the identity, not an approximation of it.
+    #[test]
+    fn scaling_to_the_same_size_is_the_identity() {
+        let src: [u8; 9] = [10, 20, 30,
+                            40, 50, 60,
+                            70, 80, 90];
+        let mut dest = [0u8; 9];
+        scale_plane(&mut dest, 3, (3, 3), &src, 3, (3, 3), 1);
+        assert_eq!(&dest[..], &src[..]);
+    }
+
+    /// Upscaling a flat-gradient plane 2x should land exactly on the values the bilinear blend
+    /// is supposed to produce at each destination texel's source-space center, not just
+    /// something in the right ballpark.
+    #[test]
+    fn upscaling_interpolates_between_source_texels() {
+        // A single row, values increasing left to right: 0, 100.
+        let src: [u8; 2] = [0, 100];
+        let mut dest = [0u8; 4];
+        scale_plane(&mut dest, 4, (4, 1), &src, 2, (2, 1), 1);
+
+        // Destination texel centers land at source x = -0.25, 0.25, 0.75, 1.25; the two texels
+        // nearest each are blended by the fractional distance between them, and the two centers
+        // past either edge are clamped to the border texel.
+        assert_eq!(&dest[..], &[0, 25, 75, 100]);
+    }
+}
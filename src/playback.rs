@@ -0,0 +1,199 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A high-level playback driver that ties a `ContainerReader` to the appropriate decoders and
+//! hands frames to the embedder, converted and timed for presentation.
+
+use std::old_io::Reader;
+use std::ops::Sub;
+use std::time::duration::Duration;
+use libc::c_long;
+
+use container::{ContainerReader, Frame, Track};
+use videodecoder::reorder::ReorderBuffer;
+
+pub mod recorder;
+
+/// A presentation timestamp, expressed in an arbitrary per-stream tick rate.
+#[derive(Clone, Copy)]
+pub struct Timestamp {
+    /// The number of ticks since the start of the stream.
+    pub ticks: i64,
+    /// The number of ticks that make up one second, for this stream.
+    pub ticks_per_second: i64,
+}
+
+impl Sub<i64> for Timestamp {
+    type Output = TimestampDelta;
+
+    fn sub(self, rhs: i64) -> TimestampDelta {
+        TimestampDelta {
+            ticks: self.ticks - rhs,
+            ticks_per_second: self.ticks_per_second,
+        }
+    }
+}
+
+/// The difference between two `Timestamp`s, convertible to a wall-clock `Duration`.
+pub struct TimestampDelta {
+    ticks: i64,
+    ticks_per_second: i64,
+}
+
+impl TimestampDelta {
+    /// Converts this tick-based delta into a wall-clock duration.
+    pub fn duration(&self) -> Duration {
+        Duration::nanoseconds(self.ticks * 1_000_000_000 / self.ticks_per_second)
+    }
+}
+
+/// Drives playback of a single container: decoding frames in order and exposing them, along with
+/// their presentation timestamps, to an embedder that is responsible for actually rendering them.
+pub struct Player {
+    /// The underlying demultiplexer. Public so that embedders can query track metadata (width,
+    /// height, sample rate, ...) that this module has no opinion about.
+    pub reader: Box<ContainerReader + 'static>,
+    video_track_number: Option<c_long>,
+    audio_track_number: Option<c_long>,
+    video_timescale: Option<u32>,
+    /// Holds decoded frames that carry a video picture until enough later pictures have arrived
+    /// to know their presentation order for certain; see `videodecoder::reorder`. Frames without
+    /// a video picture (audio-only containers, or audio-only frames within an interleaved one)
+    /// pass straight through.
+    reorder_buffer: ReorderBuffer<Frame>,
+    pending_frame: Option<Frame>,
+    pending_presentation_time: Option<Timestamp>,
+    last_presentation_time: Option<Timestamp>,
+}
+
+impl Player {
+    /// Creates a new player for the given data stream, which is claimed to be of the given MIME
+    /// type. Probes the registered container readers for one that can parse it.
+    pub fn new(data: Box<Reader + 'static>, mime_type: &str) -> Player {
+        let reader = ::container::create_reader(data, mime_type)
+            .unwrap_or_else(|| panic!("no registered container reader understands `{}`", mime_type));
+
+        let mut video_track_number = None;
+        let mut audio_track_number = None;
+        let mut video_timescale = None;
+        for index in 0..reader.track_count() {
+            let track = reader.track_by_index(index);
+            if let Some(video_track) = track.as_video_track() {
+                if video_track_number.is_none() {
+                    video_track_number = Some(track.number());
+                    video_timescale = Some(video_track.timescale());
+                }
+            }
+            if audio_track_number.is_none() && track.as_audio_track().is_some() {
+                audio_track_number = Some(track.number())
+            }
+        }
+
+        let reorder_depth = reader.video_reorder_depth();
+
+        Player {
+            reader: reader,
+            video_track_number: video_track_number,
+            audio_track_number: audio_track_number,
+            video_timescale: video_timescale,
+            reorder_buffer: ReorderBuffer::new(reorder_depth),
+            pending_frame: None,
+            pending_presentation_time: None,
+            last_presentation_time: None,
+        }
+    }
+
+    /// The track number of this container's video track, if it has one.
+    pub fn video_track_number(&self) -> Option<c_long> {
+        self.video_track_number
+    }
+
+    /// The track number of this container's audio track, if it has one.
+    pub fn audio_track_number(&self) -> Option<c_long> {
+        self.audio_track_number
+    }
+
+    /// Decodes frames from the container, buffering them internally until `advance` is called.
+    /// Separate from `advance` so that an embedder can decode ahead of the presentation clock
+    /// before blocking on it.
+    ///
+    /// Frames that carry a video picture are run through the reorder buffer first, since the
+    /// container may hand them to us in decode rather than presentation order; this may consume
+    /// several frames from the reader before one is actually ready to expose. Once the reader
+    /// reaches end of stream, the reorder buffer is drained one frame at a time, in presentation
+    /// order, before this finally returns `Err(())`.
+    pub fn decode_frame(&mut self) -> Result<(), ()> {
+        loop {
+            match self.reader.next_frame() {
+                Ok(frame) => {
+                    let presentation_timestamp =
+                        frame.video_frame.as_ref().map(|video_frame| {
+                            video_frame.presentation_timestamp()
+                        });
+                    match presentation_timestamp {
+                        Some(ticks) => {
+                            if let Some(ready) = self.reorder_buffer.push(ticks, frame) {
+                                self.set_pending_frame(ready);
+                                return Ok(())
+                            }
+                            // The reorder buffer absorbed this one; keep decoding ahead until it
+                            // releases a frame.
+                        }
+                        None => {
+                            self.set_pending_frame(frame);
+                            return Ok(())
+                        }
+                    }
+                }
+                Err(()) => {
+                    return match self.reorder_buffer.flush_one() {
+                        Some(ready) => {
+                            self.set_pending_frame(ready);
+                            Ok(())
+                        }
+                        None => Err(()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records `frame` as the next one `advance` should return, computing its presentation
+    /// timestamp from its video picture, if it has one.
+    fn set_pending_frame(&mut self, frame: Frame) {
+        let video_timescale = self.video_timescale;
+        self.pending_presentation_time = frame.video_frame.as_ref().and_then(|video_frame| {
+            video_timescale.map(|timescale| {
+                Timestamp {
+                    ticks: video_frame.presentation_timestamp(),
+                    ticks_per_second: timescale as i64,
+                }
+            })
+        });
+        self.pending_frame = Some(frame);
+    }
+
+    /// The presentation timestamp of the frame that `advance` will next return, if one has been
+    /// decoded and it carries a video picture.
+    pub fn next_frame_presentation_time(&self) -> Option<Timestamp> {
+        self.pending_presentation_time
+    }
+
+    /// The presentation timestamp of the last frame returned by `advance`.
+    pub fn last_frame_presentation_time(&self) -> Option<Timestamp> {
+        self.last_presentation_time
+    }
+
+    /// Hands back the frame most recently prepared by `decode_frame`, consuming it.
+    pub fn advance(&mut self) -> Result<Frame, ()> {
+        let frame = try!(self.pending_frame.take().ok_or(()));
+        self.last_presentation_time = self.pending_presentation_time.take();
+        Ok(frame)
+    }
+}
@@ -0,0 +1,565 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Capturing a stream to disk: a `Recorder` sits downstream of a `Player`, taking each decoded
+//! `container::Frame` through decode -> (optional scale/convert) -> encode -> mux, turning
+//! playback into a remuxing or transcoding pipeline.
+//!
+//! A `Recorder` is driven explicitly, rather than being a hidden side effect of `Player`: an
+//! embedder decodes as usual with `Player::decode_frame`/`advance`, and, only for the streams it
+//! wants to capture, passes the resulting `Frame` on to `Recorder::record_frame`.
+
+use audioencoder::{self, AudioEncoder, AudioEncoderConfig};
+use container::{AudioTrackConfig, ContainerWriter, Frame, Sample, VideoTrackConfig,
+                 WriterTrackId};
+use pixelformat::{ConvertPixelFormat, PixelFormat};
+use videodecoder::{DecodedVideoFrame, DecodedVideoFrameLockGuard};
+use videoencoder::{self, VideoEncoder, VideoEncoderConfig};
+
+/// Describes how the video track, if any, should be captured: which encoder to drive it with, and
+/// the source format the decoder is actually producing frames in, so `Recorder` knows whether a
+/// scale/convert pass is needed before encoding.
+pub struct VideoRecordingConfig {
+    /// The encoder to construct for this track, and the dimensions it should encode at.
+    pub encoder_config: VideoEncoderConfig,
+    /// The pixel format the encoder expects its input in.
+    pub encoder_pixel_format: PixelFormat<'static>,
+    /// The pixel format the video decoder actually produces frames in. May differ from
+    /// `encoder_pixel_format`, in which case frames are converted before encoding.
+    pub source_pixel_format: PixelFormat<'static>,
+    /// The dimensions the video decoder actually produces frames at. May differ from
+    /// `encoder_config`'s, in which case frames are scaled before encoding.
+    pub source_width: u16,
+    /// See `source_width`.
+    pub source_height: u16,
+}
+
+/// Describes how the audio track, if any, should be captured.
+pub struct AudioRecordingConfig {
+    /// The encoder to construct for this track.
+    pub encoder_config: AudioEncoderConfig,
+}
+
+/// Per-track state for a video recording in progress.
+struct VideoRecorderState {
+    encoder: Box<VideoEncoder + 'static>,
+    track: WriterTrackId,
+    pixel_format: PixelFormat<'static>,
+    width: u16,
+    height: u16,
+    source_width: u16,
+    source_height: u16,
+    source_pixel_format: Option<PixelFormat<'static>>,
+    /// The most recently encoded sample, held back until the next frame's presentation timestamp
+    /// arrives and its true duration (the gap to that timestamp) can be computed; see
+    /// `Recorder::record_frame` and `Recorder::finish`.
+    pending: Option<PendingVideoSample>,
+}
+
+/// An encoded video sample that hasn't been written to the container yet because its duration
+/// isn't known: a sample's duration is the gap between its own presentation timestamp and the
+/// *next* sample's, which isn't available until that next sample has been decoded.
+struct PendingVideoSample {
+    data: Vec<u8>,
+    sync: bool,
+    presentation_timestamp: i64,
+    /// The duration the encoder itself reported for this sample, used as a fallback if this
+    /// turns out to be the last sample in the stream (so there is no next timestamp to derive a
+    /// duration from); see `Recorder::finish`.
+    encoder_duration: u32,
+}
+
+/// Per-track state for an audio recording in progress.
+struct AudioRecorderState {
+    encoder: Box<AudioEncoder + 'static>,
+    track: WriterTrackId,
+}
+
+/// Drives a decode -> (optional scale/convert) -> encode -> mux pipeline, capturing the frames a
+/// `Player` produces into a `ContainerWriter`.
+pub struct Recorder {
+    writer: Box<ContainerWriter + 'static>,
+    video: Option<VideoRecorderState>,
+    audio: Option<AudioRecorderState>,
+}
+
+impl Recorder {
+    /// Builds encoders for whichever of `video`/`audio` are present, registers their tracks with
+    /// `writer`, and returns a `Recorder` ready to take frames via `record_frame`. Fails if no
+    /// registered encoder supports a requested codec.
+    pub fn new(mut writer: Box<ContainerWriter + 'static>,
+               video: Option<VideoRecordingConfig>,
+               audio: Option<AudioRecordingConfig>)
+               -> Result<Recorder, ()> {
+        let video = match video {
+            Some(config) => {
+                let encoder = try!(videoencoder::create_video_encoder(&config.encoder_config)
+                                    .ok_or(()));
+                let codec = config.encoder_config
+                                  .codec
+                                  .sample_codec(encoder.decoder_configuration());
+                let track = writer.add_video_track(VideoTrackConfig {
+                    width: config.encoder_config.width,
+                    height: config.encoder_config.height,
+                    timescale: 1000,
+                    codec: codec,
+                });
+                let needs_conversion =
+                    config.source_pixel_format != config.encoder_pixel_format ||
+                    config.source_width != config.encoder_config.width ||
+                    config.source_height != config.encoder_config.height;
+                let source_pixel_format = if needs_conversion {
+                    Some(config.source_pixel_format.clone())
+                } else {
+                    None
+                };
+                Some(VideoRecorderState {
+                    encoder: encoder,
+                    track: track,
+                    pixel_format: config.encoder_pixel_format,
+                    width: config.encoder_config.width,
+                    height: config.encoder_config.height,
+                    source_width: config.source_width,
+                    source_height: config.source_height,
+                    source_pixel_format: source_pixel_format,
+                    pending: None,
+                })
+            }
+            None => None,
+        };
+
+        let audio = match audio {
+            Some(config) => {
+                let encoder = try!(audioencoder::create_audio_encoder(&config.encoder_config)
+                                    .ok_or(()));
+                let codec = config.encoder_config
+                                  .codec
+                                  .sample_codec(encoder.decoder_configuration());
+                let track = writer.add_audio_track(AudioTrackConfig {
+                    sample_rate: config.encoder_config.sample_rate,
+                    channels: config.encoder_config.channels,
+                    codec: codec,
+                });
+                Some(AudioRecorderState {
+                    encoder: encoder,
+                    track: track,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Recorder {
+            writer: writer,
+            video: video,
+            audio: audio,
+        })
+    }
+
+    /// Encodes and muxes whichever of `frame`'s video picture and audio samples this recorder has
+    /// a track for. Frames for tracks that weren't configured are silently ignored, so an embedder
+    /// can feed every frame `Player` produces without filtering first.
+    pub fn record_frame(&mut self, frame: &Frame) -> Result<(), ()> {
+        if let (Some(video_frame), Some(state)) =
+               (frame.video_frame.as_ref(), self.video.as_mut()) {
+            let presentation_timestamp = video_frame.presentation_timestamp();
+
+            let scaled;
+            let encoded_frame: &DecodedVideoFrame = match state.source_pixel_format {
+                None => &**video_frame,
+                Some(ref source_pixel_format) => {
+                    scaled = try!(scale_video_frame(&**video_frame,
+                                                     source_pixel_format,
+                                                     (state.source_width as usize,
+                                                      state.source_height as usize),
+                                                     &state.pixel_format,
+                                                     (state.width as usize, state.height as usize)));
+                    &scaled
+                }
+            };
+
+            // No packet yet if the encoder is buffering this frame internally (to find the best
+            // place for a key frame, or to reorder for B-frames); there's nothing to mux until a
+            // later call to `encode` (or `finish`'s call to `flush`) produces one.
+            if let Some(packet) = try!(state.encoder.encode(encoded_frame)) {
+                // This frame's own duration isn't known yet (it's the gap to the *next* frame's
+                // timestamp), so write the previous pending sample, now that its duration is
+                // known, and hold this one back in its place.
+                if let Some(pending) = state.pending.take() {
+                    let duration = (presentation_timestamp - pending.presentation_timestamp) as u32;
+                    try!(self.writer.write_sample(state.track, Sample {
+                        data: &pending.data,
+                        duration: duration,
+                        sync: pending.sync,
+                    }));
+                }
+                state.pending = Some(PendingVideoSample {
+                    data: packet.data,
+                    sync: packet.sync,
+                    presentation_timestamp: presentation_timestamp,
+                    encoder_duration: packet.duration,
+                });
+            }
+        }
+
+        if let (Some(audio_samples), Some(state)) =
+               (frame.audio_samples.as_ref(), self.audio.as_mut()) {
+            let channels: Vec<_> = audio_samples.iter().map(|channel| channel.as_slice()).collect();
+            // No packet yet if the encoder is still accumulating a full frame's worth of
+            // samples; nothing to mux until a later call produces one. When it does, the packet
+            // carries its own duration, since it may cover samples from more than just this call.
+            if let Some(packet) = try!(state.encoder.encode(channels.as_slice())) {
+                try!(self.writer.write_sample(state.track, Sample {
+                    data: &packet.data,
+                    duration: packet.duration,
+                    sync: packet.sync,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes any packets buffered inside the encoders and finalizes the output container.
+    pub fn finish(mut self) -> Result<(), ()> {
+        if let Some(mut state) = self.video.take() {
+            // There's no later frame's timestamp left to derive the last recorded sample's
+            // duration from, so fall back to whatever duration the encoder itself reported for
+            // it.
+            if let Some(pending) = state.pending.take() {
+                try!(self.writer.write_sample(state.track, Sample {
+                    data: &pending.data,
+                    duration: pending.encoder_duration,
+                    sync: pending.sync,
+                }));
+            }
+            while let Some(packet) = try!(state.encoder.flush()) {
+                try!(self.writer.write_sample(state.track, Sample {
+                    data: &packet.data,
+                    duration: packet.duration,
+                    sync: packet.sync,
+                }));
+            }
+        }
+        if let Some(mut state) = self.audio.take() {
+            while let Some(packet) = try!(state.encoder.flush()) {
+                try!(self.writer.write_sample(state.track, Sample {
+                    data: &packet.data,
+                    duration: packet.duration,
+                    sync: packet.sync,
+                }));
+            }
+        }
+        self.writer.finish()
+    }
+}
+
+/// A decoded video frame's pixels, scaled and/or converted into a new pixel format, held as owned
+/// buffers so it can be passed to `VideoEncoder::encode` as a standalone `DecodedVideoFrame`.
+struct ScaledVideoFrame {
+    pixel_format: PixelFormat<'static>,
+    planes: Vec<Vec<u8>>,
+    strides: Vec<i32>,
+    presentation_timestamp: i64,
+}
+
+impl DecodedVideoFrame for ScaledVideoFrame {
+    fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format.clone()
+    }
+
+    fn stride(&self, plane: usize) -> i32 {
+        self.strides[plane]
+    }
+
+    fn presentation_timestamp(&self) -> i64 {
+        self.presentation_timestamp
+    }
+
+    fn lock<'a>(&'a self) -> Box<DecodedVideoFrameLockGuard + 'a> {
+        Box::new(ScaledVideoFrameLockGuard { frame: self })
+    }
+}
+
+struct ScaledVideoFrameLockGuard<'a> {
+    frame: &'a ScaledVideoFrame,
+}
+
+impl<'a> DecodedVideoFrameLockGuard for ScaledVideoFrameLockGuard<'a> {
+    fn pixels<'b>(&'b self, plane: usize) -> &'b [u8] {
+        &self.frame.planes[plane]
+    }
+}
+
+/// Scales and/or converts `frame` from `src_format`/`src_dims` to `dest_format`/`dest_dims`,
+/// packing the result into freshly-allocated, tightly-strided plane buffers.
+fn scale_video_frame(frame: &DecodedVideoFrame,
+                     src_format: &PixelFormat<'static>,
+                     src_dims: (usize, usize),
+                     dest_format: &PixelFormat<'static>,
+                     dest_dims: (usize, usize))
+                     -> Result<ScaledVideoFrame, ()> {
+    let lock = frame.lock();
+    let mut src_pixels = Vec::with_capacity(src_format.planes());
+    let mut src_strides = Vec::with_capacity(src_format.planes());
+    for plane in 0..src_format.planes() {
+        src_pixels.push(lock.pixels(plane));
+        src_strides.push(frame.stride(plane) as usize);
+    }
+
+    let (dest_strides, plane_lengths) = plane_layout(dest_format, dest_dims);
+    let mut planes: Vec<Vec<u8>> = plane_lengths.iter().map(|&length| vec![0u8; length]).collect();
+    {
+        let mut dest_pixels: Vec<_> = planes.iter_mut().map(|plane| plane.as_mut_slice()).collect();
+        try!(src_format.convert_scaled(dest_format,
+                                       dest_pixels.as_mut_slice(),
+                                       dest_strides.as_slice(),
+                                       dest_dims,
+                                       src_pixels.as_slice(),
+                                       src_strides.as_slice(),
+                                       src_dims));
+    }
+
+    Ok(ScaledVideoFrame {
+        pixel_format: dest_format.clone(),
+        planes: planes,
+        strides: dest_strides.iter().map(|&stride| stride as i32).collect(),
+        presentation_timestamp: frame.presentation_timestamp(),
+    })
+}
+
+/// Computes tightly-packed per-plane strides and buffer lengths for `format` at `dims`, handling
+/// 4:2:0 chroma subsampling for `I420`/`NV12`.
+fn plane_layout(format: &PixelFormat<'static>, dims: (usize, usize)) -> (Vec<usize>, Vec<usize>) {
+    let (width, height) = dims;
+    let chroma_width = (width + 1) / 2;
+    let chroma_height = (height + 1) / 2;
+    match *format {
+        PixelFormat::I420 => {
+            (vec![width, chroma_width, chroma_width],
+             vec![width * height, chroma_width * chroma_height, chroma_width * chroma_height])
+        }
+        PixelFormat::NV12 => {
+            (vec![width, chroma_width * 2],
+             vec![width * height, chroma_width * 2 * chroma_height])
+        }
+        PixelFormat::Rgb24 => (vec![width * 3], vec![width * 3 * height]),
+        PixelFormat::Indexed(_) => (vec![width], vec![width * height]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use audioencoder::{AudioEncoder, EncodedPacket as AudioEncodedPacket};
+    use container::{AudioTrackConfig, ContainerWriter, Frame, Sample, VideoTrackConfig,
+                     WriterTrackId};
+    use pixelformat::PixelFormat;
+    use videodecoder::{DecodedVideoFrame, DecodedVideoFrameLockGuard};
+    use videoencoder::{EncodedPacket as VideoEncodedPacket, VideoEncoder};
+
+    use super::{AudioRecorderState, Recorder, VideoRecorderState};
+
+    /// A `ContainerWriter` that just records, in order, the `(track, duration, sync)` of every
+    /// sample handed to `write_sample`, so tests can assert on what a `Recorder` actually muxed.
+    struct FakeWriter {
+        written: Rc<RefCell<Vec<(u32, u32, bool)>>>,
+    }
+
+    impl ContainerWriter for FakeWriter {
+        fn add_video_track(&mut self, _config: VideoTrackConfig) -> WriterTrackId {
+            WriterTrackId(0)
+        }
+        fn add_audio_track(&mut self, _config: AudioTrackConfig) -> WriterTrackId {
+            WriterTrackId(1)
+        }
+        fn write_sample(&mut self, track: WriterTrackId, sample: Sample) -> Result<(), ()> {
+            let WriterTrackId(id) = track;
+            self.written.borrow_mut().push((id, sample.duration, sample.sync));
+            Ok(())
+        }
+        fn finish(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    /// A `VideoEncoder` that returns a scripted sequence of responses, one per `encode` call, to
+    /// exercise a real encoder's ability to buffer a frame internally and return `Ok(None)`.
+    struct ScriptedVideoEncoder {
+        responses: Vec<Option<(Vec<u8>, u32)>>,
+        next: usize,
+    }
+
+    impl VideoEncoder for ScriptedVideoEncoder {
+        fn encode(&mut self, _frame: &DecodedVideoFrame) -> Result<Option<VideoEncodedPacket>, ()> {
+            let response = self.responses[self.next].clone();
+            self.next += 1;
+            Ok(response.map(|(data, duration)| VideoEncodedPacket {
+                data: data,
+                duration: duration,
+                sync: true,
+            }))
+        }
+        fn decoder_configuration(&self) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    struct FakeVideoFrame {
+        presentation_timestamp: i64,
+    }
+
+    struct FakeVideoFrameLockGuard;
+
+    impl DecodedVideoFrameLockGuard for FakeVideoFrameLockGuard {
+        fn pixels<'a>(&'a self, _plane: usize) -> &'a [u8] {
+            &[]
+        }
+    }
+
+    impl DecodedVideoFrame for FakeVideoFrame {
+        fn pixel_format(&self) -> PixelFormat {
+            PixelFormat::Rgb24
+        }
+        fn stride(&self, _plane: usize) -> i32 {
+            0
+        }
+        fn presentation_timestamp(&self) -> i64 {
+            self.presentation_timestamp
+        }
+        fn lock<'a>(&'a self) -> Box<DecodedVideoFrameLockGuard + 'a> {
+            Box::new(FakeVideoFrameLockGuard)
+        }
+    }
+
+    fn video_frame(presentation_timestamp: i64) -> Frame {
+        Frame {
+            video_frame: Some(Box::new(FakeVideoFrame {
+                presentation_timestamp: presentation_timestamp,
+            })),
+            audio_samples: None,
+        }
+    }
+
+    fn recorder_with_video(responses: Vec<Option<(Vec<u8>, u32)>>)
+                           -> (Recorder, Rc<RefCell<Vec<(u32, u32, bool)>>>) {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let writer = FakeWriter { written: written.clone() };
+        let recorder = Recorder {
+            writer: Box::new(writer),
+            video: Some(VideoRecorderState {
+                encoder: Box::new(ScriptedVideoEncoder { responses: responses, next: 0 }),
+                track: WriterTrackId(0),
+                pixel_format: PixelFormat::Rgb24,
+                width: 4,
+                height: 4,
+                source_width: 4,
+                source_height: 4,
+                source_pixel_format: None,
+                pending: None,
+            }),
+            audio: None,
+        };
+        (recorder, written)
+    }
+
+    /// A sample's duration is the gap to the *next* frame's presentation timestamp, not a fixed
+    /// or encoder-reported value; the last sample in the stream has no next timestamp to derive
+    /// one from, so `finish` must fall back to whatever the encoder itself reported.
+    #[test]
+    fn video_sample_duration_is_the_gap_to_the_next_frame_and_falls_back_at_finish() {
+        let (mut recorder, written) = recorder_with_video(vec![
+            Some((vec![1], 999)),
+            Some((vec![2], 999)),
+            Some((vec![3], 42)),
+        ]);
+
+        recorder.record_frame(&video_frame(0)).unwrap();
+        recorder.record_frame(&video_frame(100)).unwrap();
+        recorder.record_frame(&video_frame(130)).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(*written.borrow(), vec![(0, 100, true), (0, 30, true), (0, 42, true)]);
+    }
+
+    /// If the encoder buffers a frame internally and returns `Ok(None)`, there is no packet to
+    /// mux for that call; the next frame that does yield a packet must still be muxed normally,
+    /// not skipped or duplicated.
+    #[test]
+    fn a_buffered_frame_that_yields_no_packet_is_not_muxed() {
+        let (mut recorder, written) = recorder_with_video(vec![
+            None,
+            Some((vec![1], 999)),
+        ]);
+
+        recorder.record_frame(&video_frame(0)).unwrap();
+        assert!(written.borrow().is_empty());
+
+        recorder.record_frame(&video_frame(50)).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(*written.borrow(), vec![(0, 999, true)]);
+    }
+
+    /// A `AudioEncoder` that returns a scripted sequence of responses, one per `encode` call, to
+    /// exercise an encoder that accumulates samples internally before it has a full frame's worth
+    /// to emit.
+    struct ScriptedAudioEncoder {
+        responses: Vec<Option<(Vec<u8>, u32)>>,
+        next: usize,
+    }
+
+    impl AudioEncoder for ScriptedAudioEncoder {
+        fn encode(&mut self, _samples: &[&[f32]]) -> Result<Option<AudioEncodedPacket>, ()> {
+            let response = self.responses[self.next].clone();
+            self.next += 1;
+            Ok(response.map(|(data, duration)| {
+                AudioEncodedPacket { data: data, duration: duration, sync: true }
+            }))
+        }
+        fn decoder_configuration(&self) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    fn audio_frame(sample_count: usize) -> Frame {
+        Frame {
+            video_frame: None,
+            audio_samples: Some(vec![vec![0.0f32; sample_count]]),
+        }
+    }
+
+    /// A frame whose encoder call returns `Ok(None)` (still accumulating samples for a full
+    /// frame) must not be muxed; once the encoder does emit a packet, it's muxed using the
+    /// packet's own reported duration, not the sample count of whichever call happened to trigger
+    /// it, since a packet that took two calls to assemble covers both calls' samples.
+    #[test]
+    fn a_buffered_audio_block_that_yields_no_packet_is_not_muxed() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let writer = FakeWriter { written: written.clone() };
+        let mut recorder = Recorder {
+            writer: Box::new(writer),
+            video: None,
+            audio: Some(AudioRecorderState {
+                encoder: Box::new(ScriptedAudioEncoder {
+                    responses: vec![None, Some((vec![9], 768))],
+                    next: 0,
+                }),
+                track: WriterTrackId(1),
+            }),
+        };
+
+        recorder.record_frame(&audio_frame(512)).unwrap();
+        assert!(written.borrow().is_empty());
+
+        recorder.record_frame(&audio_frame(256)).unwrap();
+        assert_eq!(*written.borrow(), vec![(1, 768, true)]);
+    }
+}
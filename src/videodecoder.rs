@@ -0,0 +1,50 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decoding of coded video packets into raw pixel data.
+
+use pixelformat::PixelFormat;
+
+pub mod reorder;
+
+/// A video decoder for a single codec (H.264, VP9, Theora, ...).
+///
+/// Container readers decode their own video tracks internally (see
+/// `container::ContainerReader::video_reorder_depth`); `playback::Player` does not construct
+/// decoders itself, and there is no codec-id registry of decoders.
+pub trait VideoDecoder {
+    /// Decodes a single coded packet, returning the decoded frame.
+    ///
+    /// Not every call necessarily returns a displayable frame immediately: some codecs buffer
+    /// internally and only emit a frame once enough reference data has arrived.
+    fn decode_frame(&mut self, data: &[u8]) -> Result<Box<DecodedVideoFrame + 'static>, ()>;
+}
+
+/// A single decoded video frame, still in its native pixel format.
+pub trait DecodedVideoFrame {
+    /// The pixel format the decoder produced this frame in.
+    fn pixel_format(&self) -> PixelFormat;
+    /// The stride, in bytes, of the given plane.
+    fn stride(&self, plane: usize) -> i32;
+    /// This frame's presentation timestamp, in the track's timescale units. Decode order is not
+    /// necessarily presentation order (see `reorder`), so this is what determines when the frame
+    /// should actually be shown, as distinct from when it was decoded.
+    fn presentation_timestamp(&self) -> i64;
+    /// Locks the frame's pixel data for reading, returning a guard that hands out one slice per
+    /// plane. Decoders that own GPU or otherwise non-trivially-mapped buffers can use this to
+    /// defer the (possibly expensive) CPU mapping until it's actually needed.
+    fn lock<'a>(&'a self) -> Box<DecodedVideoFrameLockGuard + 'a>;
+}
+
+/// A guard returned by `DecodedVideoFrame::lock`, giving access to the locked plane data for as
+/// long as the guard is alive.
+pub trait DecodedVideoFrameLockGuard {
+    /// Returns the raw pixel data of the given plane.
+    fn pixels<'a>(&'a self, plane: usize) -> &'a [u8];
+}
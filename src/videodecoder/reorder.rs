@@ -0,0 +1,104 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Picture reordering: codecs with B-frames emit pictures in decode order, which is not the
+//! same as presentation order. `ReorderBuffer` sits between decoding and presentation, holding
+//! decoded pictures until enough of them have arrived to know their presentation order for
+//! certain, and then releasing them one at a time, smallest presentation timestamp first.
+
+/// A bounded reorder queue, generic over whatever payload the caller wants reordered by
+/// presentation timestamp (typically a decoded video frame, or, in `playback::Player`, a whole
+/// demultiplexed `container::Frame`).
+pub struct ReorderBuffer<T> {
+    /// The number of frames that must be buffered before the earliest one is guaranteed to be
+    /// the next one in presentation order; see `container::ContainerReader::video_reorder_depth`.
+    capacity: usize,
+    /// Buffered `(presentation_timestamp, value)` pairs, kept sorted by timestamp.
+    pending: Vec<(i64, T)>,
+}
+
+impl<T> ReorderBuffer<T> {
+    /// Creates a new, empty buffer sized for a codec whose maximum reorder distance is
+    /// `capacity` frames. A `capacity` of zero means the codec never reorders (no B-frames), so
+    /// `push` releases each value as soon as it's inserted, rather than holding anything back.
+    pub fn new(capacity: usize) -> ReorderBuffer<T> {
+        ReorderBuffer {
+            capacity: capacity,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Inserts a newly-decoded value at the presentation timestamp it should eventually be
+    /// released at. Once the buffer holds more than `capacity` entries, the earliest one (by
+    /// presentation timestamp) is popped off and returned; otherwise, `None` is returned and the
+    /// caller should keep decoding ahead before expecting a frame back.
+    pub fn push(&mut self, presentation_timestamp: i64, value: T) -> Option<T> {
+        let position = match self.pending.binary_search_by_key(&presentation_timestamp,
+                                                                |entry| entry.0) {
+            Ok(position) | Err(position) => position,
+        };
+        self.pending.insert(position, (presentation_timestamp, value));
+
+        if self.pending.len() > self.capacity {
+            Some(self.pending.remove(0).1)
+        } else {
+            None
+        }
+    }
+
+    /// Releases the single earliest-by-presentation-timestamp buffered value, if any. Call this
+    /// repeatedly at end of stream to drain whatever `push` hasn't released yet, in order.
+    pub fn flush_one(&mut self) -> Option<T> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.pending.remove(0).1)
+        }
+    }
+
+    /// Whether every buffered value has been released.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReorderBuffer;
+
+    /// A codec with no B-frames (`capacity` zero) should never hold a frame back: each `push`
+    /// must release that same value immediately, not wait for a second one to arrive.
+    #[test]
+    fn zero_capacity_releases_immediately() {
+        let mut buffer = ReorderBuffer::new(0);
+        assert_eq!(buffer.push(0, "a"), Some("a"));
+        assert_eq!(buffer.push(1, "b"), Some("b"));
+        assert!(buffer.is_empty());
+    }
+
+    /// With a nonzero capacity, `push` should hold values back until more than `capacity` are
+    /// buffered, and then release them smallest-presentation-timestamp first, even if they
+    /// arrived out of order (as decode order is for a stream with B-frames).
+    #[test]
+    fn nonzero_capacity_releases_in_presentation_order() {
+        let mut buffer = ReorderBuffer::new(2);
+
+        // Decode order: 0, 3, 1, 2 (frame 3, a B-frame reference, decoded ahead of 1 and 2).
+        assert_eq!(buffer.push(0, "pts0"), None);
+        assert_eq!(buffer.push(3, "pts3"), None);
+        assert_eq!(buffer.push(1, "pts1"), Some("pts0"));
+        assert_eq!(buffer.push(2, "pts2"), Some("pts1"));
+        assert!(!buffer.is_empty());
+
+        assert_eq!(buffer.flush_one(), Some("pts2"));
+        assert_eq!(buffer.flush_one(), Some("pts3"));
+        assert_eq!(buffer.flush_one(), None);
+        assert!(buffer.is_empty());
+    }
+}
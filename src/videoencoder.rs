@@ -0,0 +1,82 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encoding of raw decoded pictures into coded video packets: the write-side counterpart of
+//! `videodecoder`.
+
+use container::CodecId;
+use videodecoder::DecodedVideoFrame;
+
+/// A single coded packet produced by a `VideoEncoder`, ready to be handed to
+/// `container::ContainerWriter::write_sample`.
+pub struct EncodedPacket {
+    /// The coded payload, exactly as it should appear in the output file.
+    pub data: Vec<u8>,
+    /// This packet's duration, in the track's timescale units.
+    pub duration: u32,
+    /// Whether this packet is usable as a random-access point (an IDR/key frame).
+    pub sync: bool,
+}
+
+/// A video encoder for a single codec (H.264, VP9, ...).
+///
+/// Implementations are registered by codec id and instantiated via `create_video_encoder` as a
+/// recording pipeline (see `playback::recorder::Recorder`) sets up its output tracks.
+pub trait VideoEncoder {
+    /// Encodes a single decoded picture, returning the coded packet if one is ready.
+    ///
+    /// Not every call necessarily returns a packet immediately: some encoders buffer frames
+    /// internally (to find the best place for key frames, or to reorder for B-frames) and only
+    /// emit a packet once enough frames have arrived, in which case this returns `Ok(None)` until
+    /// one is; see also `flush`, for packets still buffered at end of stream.
+    fn encode(&mut self, frame: &DecodedVideoFrame) -> Result<Option<EncodedPacket>, ()>;
+
+    /// Flushes one buffered packet, if any, from an encoder that doesn't emit its packets
+    /// immediately. Call this repeatedly at end of stream until it returns `Ok(None)`.
+    fn flush(&mut self) -> Result<Option<EncodedPacket>, ()> {
+        Ok(None)
+    }
+
+    /// The codec-specific decoder configuration record (an `avcC`, `hvcC`, or `vpcC` payload,
+    /// depending on codec) describing how to decode the packets this encoder produces. Must be
+    /// available as soon as the encoder is constructed, since muxers need it to write a track's
+    /// sample description before any samples arrive.
+    fn decoder_configuration(&self) -> Vec<u8>;
+}
+
+/// Parameters needed to construct a `VideoEncoder`.
+pub struct VideoEncoderConfig {
+    /// The codec to encode to.
+    pub codec: CodecId,
+    /// The coded width of the video, in pixels.
+    pub width: u16,
+    /// The coded height of the video, in pixels.
+    pub height: u16,
+}
+
+/// A constructor that attempts to build a `VideoEncoder` for the given configuration, returning
+/// `None` if this implementation doesn't support the requested codec.
+pub type VideoEncoderConstructor = fn(&VideoEncoderConfig) -> Option<Box<VideoEncoder + 'static>>;
+
+/// The video encoders `rust-media` knows how to construct, tried in order by
+/// `create_video_encoder`. Concrete encoders push their constructor onto this list; none are
+/// registered yet, since this tree only implements the encoding *interface* and the MP4 muxer it
+/// feeds.
+pub static REGISTERED_VIDEO_ENCODERS: &'static [VideoEncoderConstructor] = &[];
+
+/// Tries each registered video encoder in turn, returning the first one that claims to support
+/// `config.codec`.
+pub fn create_video_encoder(config: &VideoEncoderConfig) -> Option<Box<VideoEncoder + 'static>> {
+    for constructor in REGISTERED_VIDEO_ENCODERS.iter() {
+        if let Some(encoder) = constructor(config) {
+            return Some(encoder)
+        }
+    }
+    None
+}